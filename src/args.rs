@@ -1,7 +1,9 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use smart_default::SmartDefault;
 use std::{env, path::PathBuf};
 
+use crate::{Age, ConfigFormat, ExportFormat, PathRemap};
+
 #[derive(Debug, Parser, SmartDefault)]
 #[command(author, version, about)]
 pub struct Args {
@@ -13,9 +15,92 @@ pub struct Args {
     )]
     pub config_path: PathBuf,
 
+    /// The config file's format, if its extension doesn't tell
+    #[arg(long = "format", value_enum)]
+    pub config_format: Option<ConfigFormat>,
+
     /// Whether to run as systemd service
     #[arg(long = "systemd")]
     pub as_systemd_service: bool,
+
+    /// Run a command instead of the daemon
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Inspect and maintain the index of remembered tags
+    #[command(subcommand)]
+    Index(IndexCommand),
+    /// List links whose sources have identical content
+    Duplicates {
+        /// Only check the rule with this name
+        #[arg(long)]
+        rule: Option<String>,
+    },
+    /// Write the membership of every rule to a file, or stdout
+    Export {
+        /// Where to write it, instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Defaults to the output's extension, or JSON
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+    },
+    /// Tag the files listed in an exported file
+    Import {
+        input: PathBuf,
+        /// Defaults to the input's extension, or JSON
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+        /// Replace a root of the exported paths, e.g. `/home/alice/Samples=/mnt/nas/Samples`
+        #[arg(long, value_name = "FROM=TO")]
+        remap: Vec<PathRemap>,
+    },
+    /// Work with config files, without loading the config
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Check that the config loads, with its scripts compiled, then exit
+    CheckConfig,
+    /// Link and clean every rule once, without watching, then print what was done
+    Sync,
+    /// Print the running daemon's recent events, as JSON lines
+    Events {
+        /// Keep printing new events as they happen
+        #[arg(short, long)]
+        follow: bool,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum IndexCommand {
+    /// List remembered content, with its tags and where it was seen
+    List,
+    /// Apply the remembered tags to a file, by tagging it for each rule
+    Apply { path: PathBuf },
+    /// Forget the tags remembered for the content of a file
+    Forget { path: PathBuf },
+    /// Forget paths that no longer exist, and optionally content not seen for a while
+    Prune {
+        /// Also forget content last seen longer ago than this, e.g. `90d`
+        #[arg(long)]
+        older_than: Option<Age>,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ConfigCommand {
+    /// Translate a config file to another format
+    Convert {
+        input: PathBuf,
+        /// Where to write it, instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Defaults to the output's extension
+        #[arg(long, value_enum)]
+        to: Option<ConfigFormat>,
+    },
 }
 
 // impl Args {
@@ -24,7 +109,7 @@ pub struct Args {
 //     }
 // }
 
-fn default_config_path() -> PathBuf {
+pub(crate) fn default_config_path() -> PathBuf {
     let config_dir = env::var_os("XDG_CONFIG_HOME")
         .map(PathBuf::from)
         .unwrap_or_else(|| {
@@ -0,0 +1,296 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    os::unix::net::UnixStream,
+    path::{self, Path},
+    sync::Arc,
+};
+
+use anyhow::Context;
+
+use crate::{
+    actions::{deduplicating::find_duplicates, tagging::tag_source, Action, ActionReport},
+    collect_membership, import_membership, read_membership, send_shutdown, write_membership,
+    Command, Config, ConfigCommand, ConfigFormat, ContentKey, Dispatcher, ExportFormat, HashCache,
+    IndexCommand, PathRemap, RawConfig, TagIndex,
+};
+
+/// Run a command given on the command line, instead of the daemon.
+pub async fn run_command(config: &Arc<Config>, command: &Command) -> anyhow::Result<()> {
+    match command {
+        Command::Index(index_command) => run_index_command(config, index_command),
+        Command::Duplicates { rule } => run_duplicates_command(config, rule.as_deref()),
+        Command::Export { output, format } => {
+            run_export_command(config, output.as_deref(), *format)
+        }
+        Command::Import {
+            input,
+            format,
+            remap,
+        } => run_import_command(config, input, *format, remap),
+        // `run` handles these before loading the config, with the global `--format`
+        Command::Config(config_command) => run_config_command(config_command, None),
+        Command::CheckConfig => run_check_config_command(config),
+        Command::Sync => run_sync_command(config).await,
+        Command::Events { follow } => run_events_command(config, *follow),
+    }
+}
+
+/// Run a `filetags config` command, which doesn't need the config to be loaded.
+/// `format` is the global `--format`, which applies to the config files they read.
+pub fn run_config_command(
+    command: &ConfigCommand,
+    format: Option<ConfigFormat>,
+) -> anyhow::Result<()> {
+    match command {
+        ConfigCommand::Convert { input, output, to } => {
+            run_config_convert_command(input, format, output.as_deref(), *to)
+        }
+    }
+}
+
+/// Run `filetags config convert`.
+///
+/// The input is checked to be a valid config, then translated as it's written, so that paths
+/// aren't expanded, settings aren't merged with the defaults, and includes are kept as entries.
+/// It's in the format given by `from`, or by its extension.
+fn run_config_convert_command(
+    input: &Path,
+    from: Option<ConfigFormat>,
+    output: Option<&Path>,
+    to: Option<ConfigFormat>,
+) -> anyhow::Result<()> {
+    let from = from
+        .or_else(|| ConfigFormat::from_path(input))
+        .unwrap_or_default();
+    let to = to
+        .or_else(|| output.and_then(ConfigFormat::from_path))
+        .context("no format to convert to, give --to or an output with a known extension")?;
+    let contents =
+        fs::read_to_string(input).with_context(|| format!("reading config file {:?}", input))?;
+    from.parse::<RawConfig>(&contents)?;
+    let value: serde_json::Value = from.parse(&contents)?;
+    let converted = to.to_string(&value)?;
+
+    match output {
+        Some(output) => fs::write(output, converted)
+            .with_context(|| format!("writing config file {:?}", output))?,
+        None => io::stdout().lock().write_all(converted.as_bytes())?,
+    }
+
+    Ok(())
+}
+
+/// Run `filetags check-config`. The config was loaded, and its scripts compiled, before any
+/// command runs, so this only checks what's left for the daemon to find out.
+fn run_check_config_command(config: &Config) -> anyhow::Result<()> {
+    config.check_matchers()?;
+    let scripts = config.rules.iter().filter(|r| r.script.is_some()).count();
+    println!(
+        "config is valid: {} rules, {} with scripts, from {} files",
+        config.rules.len(),
+        scripts,
+        config.sources.len()
+    );
+    for source in &config.sources {
+        println!("  {}", source.display());
+    }
+
+    Ok(())
+}
+
+/// Run `filetags sync`, performing the daemon's startup actions and printing their report.
+async fn run_sync_command(config: &Arc<Config>) -> anyhow::Result<()> {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let dispatcher = Dispatcher::new(rx, tx, Arc::clone(config))?;
+    let mut report = ActionReport::default();
+    for action in [
+        Action::MakeNecessaryDirs,
+        Action::CleanAll,
+        Action::SymlinkAll,
+    ] {
+        report.merge(dispatcher.perform(action).await?);
+        if !report.errors.is_empty() {
+            break;
+        }
+    }
+    send_shutdown(&dispatcher.tx);
+    dispatcher.rx_handle.await.context("joining responder")??;
+
+    let mut stdout = io::stdout().lock();
+    for path in &report.created {
+        writeln!(stdout, "created {}", path.display())?;
+    }
+    for path in &report.removed {
+        writeln!(stdout, "removed {}", path.display())?;
+    }
+    for path in &report.skipped {
+        writeln!(stdout, "skipped {}", path.display())?;
+    }
+    writeln!(stdout, "{}", report)?;
+    report.into_result()?;
+
+    Ok(())
+}
+
+/// Run `filetags events`, copying the events served by the daemon to stdout.
+fn run_events_command(config: &Config, follow: bool) -> anyhow::Result<()> {
+    let socket_path = config
+        .misc
+        .event_socket
+        .as_ref()
+        .context("no `misc.event_socket` is set, so the daemon doesn't serve events")?;
+    let mut stream = UnixStream::connect(socket_path).with_context(|| {
+        format!(
+            "connecting to the daemon at {:?}, is it running?",
+            socket_path
+        )
+    })?;
+    let request = match follow {
+        true => "follow",
+        false => "recent",
+    };
+    writeln!(stream, "{}", request).context("sending request")?;
+    io::copy(&mut stream, &mut io::stdout().lock()).context("printing events")?;
+
+    Ok(())
+}
+
+/// Run `filetags export`.
+fn run_export_command(
+    config: &Config,
+    output: Option<&Path>,
+    format: Option<ExportFormat>,
+) -> anyhow::Result<()> {
+    let records = collect_membership(config)?;
+    match output {
+        Some(output) => {
+            let format = format.unwrap_or_else(|| ExportFormat::from_path(output));
+            let file = File::create(output).with_context(|| format!("creating {:?}", output))?;
+            write_membership(&records, format, BufWriter::new(file))?;
+            eprintln!("exported {} members to {}", records.len(), output.display());
+        }
+        None => write_membership(
+            &records,
+            format.unwrap_or(ExportFormat::Json),
+            io::stdout().lock(),
+        )?,
+    }
+
+    Ok(())
+}
+
+/// Run `filetags import`, and print a report of what couldn't be imported.
+fn run_import_command(
+    config: &Config,
+    input: &Path,
+    format: Option<ExportFormat>,
+    remaps: &[PathRemap],
+) -> anyhow::Result<()> {
+    let format = format.unwrap_or_else(|| ExportFormat::from_path(input));
+    let file = File::open(input).with_context(|| format!("opening {:?}", input))?;
+    let records = read_membership(BufReader::new(file), format)?;
+    let report = import_membership(config, &records, remaps)?;
+
+    println!(
+        "tagged {}, already tagged {}, not found {}, skipped {}",
+        report.tagged.len(),
+        report.already_tagged.len(),
+        report.not_found.len(),
+        report.skipped.len()
+    );
+    for path in &report.not_found {
+        println!("not found: {}", path.display());
+    }
+    for (record, reason) in &report.skipped {
+        println!(
+            "skipped: {} in rule {:?}: {}",
+            record.watch_dir.join(&record.path).display(),
+            record.rule,
+            reason
+        );
+    }
+
+    Ok(())
+}
+
+/// Run `filetags duplicates`.
+fn run_duplicates_command(config: &Config, rule_name: Option<&str>) -> anyhow::Result<()> {
+    let rule_indices = match rule_name {
+        Some(name) => vec![config
+            .rule_idx(name)
+            .with_context(|| format!("no such rule: {:?}", name))?],
+        None => (0..config.rules.len()).collect(),
+    };
+
+    let mut hashes = HashCache::default();
+    for rule_idx in rule_indices {
+        for group in find_duplicates(config, rule_idx, &mut hashes)? {
+            println!(
+                "rule {:?}: {} links to {}",
+                config.rules[rule_idx].name,
+                group.links.len(),
+                group.key
+            );
+            for (symlink_path, src_path) in &group.links {
+                println!("    {} -> {}", symlink_path.display(), src_path.display());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a subcommand of `filetags index`.
+fn run_index_command(config: &Config, command: &IndexCommand) -> anyhow::Result<()> {
+    let mut index = TagIndex::load(&config.misc.index_path).context("loading tag index")?;
+
+    match command {
+        IndexCommand::List => {
+            for entry in index.entries() {
+                let tags = entry.tags.iter().cloned().collect::<Vec<_>>().join(", ");
+                println!("{} [{}]", entry.key, tags);
+                for path in &entry.paths {
+                    println!("    {}", path.display());
+                }
+            }
+        }
+        IndexCommand::Apply { path } => {
+            let path = path::absolute(path)?;
+            let key = ContentKey::of_file(&path)?;
+            let entry = index
+                .get(&key)
+                .with_context(|| format!("no tags are remembered for {:?}", path))?;
+            let mut path = path.clone();
+            for tag in &entry.tags {
+                let Some(rule_idx) = config.rule_idx(tag) else {
+                    println!("skipping {:?}, no such rule", tag);
+                    continue;
+                };
+                path = tag_source(config, None, rule_idx, &path)?;
+                println!("tagged {} for rule {:?}", path.display(), tag);
+            }
+        }
+        IndexCommand::Forget { path } => {
+            let path = path::absolute(path)?;
+            let key = match index.get_by_path(&path) {
+                Some(entry) => entry.key,
+                None => ContentKey::of_file(&path)?,
+            };
+            anyhow::ensure!(index.forget(key), "no tags are remembered for {:?}", path);
+            index.save()?;
+            println!("forgot {}", key);
+        }
+        IndexCommand::Prune { older_than } => {
+            let paths = index.prune_missing();
+            println!("forgot {} missing paths", paths);
+            if let Some(older_than) = older_than {
+                let entries = index.prune_older_than(older_than.0);
+                println!("forgot {} entries older than {}", entries, older_than);
+            }
+            index.save()?;
+        }
+    }
+
+    Ok(())
+}
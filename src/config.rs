@@ -1,12 +1,35 @@
-use std::{env::VarError, fs, path::PathBuf, sync::Arc};
+use std::{
+    collections::HashSet,
+    env::VarError,
+    mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use anyhow::Context;
 use regex::Regex;
-use serde::{de::Error, Deserialize, Deserializer, Serialize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 use shellexpand::LookupError;
 use smart_default::SmartDefault;
 
-use crate::{args::Args, utils};
+use crate::{args::Args, index::default_index_path, utils, Error, Matcher, Matchers, Result};
+
+mod builder;
+mod format;
+mod include;
+mod playlist;
+mod predicates;
+mod query;
+mod script;
+mod template;
+
+pub use builder::{ConfigBuilder, RuleBuilder};
+pub use format::ConfigFormat;
+pub use playlist::{Playlist, PlaylistFormat, PlaylistSort};
+pub use predicates::{Age, ByteSize, MimePattern};
+pub use query::{Expr, Query};
+pub use script::{Script, ScriptVerdict};
+use template::for_each_field;
 
 // Config /////////////////////////////////////////////////////////////////////
 
@@ -16,17 +39,54 @@ use crate::{args::Args, utils};
 pub struct Config {
     pub misc: MiscSettings,
     pub rules: Vec<Rule>,
+
+    /// The matchers registered in code, see `register_matcher`.
+    #[serde(skip)]
+    pub matchers: Matchers,
+
+    /// The files the config was loaded from, the main one first, see `Config::load`.
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
 }
 
 impl Config {
-    pub fn create(args: &Args) -> anyhow::Result<Arc<Self>> {
-        let path: PathBuf = args.config_path.clone();
-        let contents: String = fs::read_to_string(path).context("reading config file")?;
-        let mut config: Self = serde_yml::from_str(&contents)?;
+    /// Loads the config file, in the format given by `--format`, or else guessed from its
+    /// extension, or else YAML, along with the files it includes.
+    pub fn create(args: &Args) -> Result<Arc<Self>> {
+        let mut config = Config::load(&args.config_path, args.config_format)?;
         config.misc.systemd_service = args.as_systemd_service;
 
         Ok(Arc::new(config))
     }
+
+    /// Returns the index of the rule with the given name.
+    pub fn rule_idx(&self, name: &str) -> Option<usize> {
+        self.rules.iter().position(|rule| rule.name == name)
+    }
+
+    /// Registers a matcher that rules can refer to by name in their `matchers`.
+    pub fn register_matcher(&mut self, name: impl Into<String>, matcher: impl Matcher + 'static) {
+        self.matchers.insert(name, matcher);
+    }
+
+    /// Checks that every matcher named by a rule is registered.
+    pub fn check_matchers(&self) -> Result<()> {
+        for rule in &self.rules {
+            if let Some(name) = rule.matchers.iter().find(|m| !self.matchers.contains(m)) {
+                return Err(Error::Config(format!(
+                    "rule {:?}: no matcher is registered as {:?}",
+                    rule.name, name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates a config in code, see `ConfigBuilder`.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
 }
 
 // MiscSettings ///////////////////////////////////////////////////////////////
@@ -36,6 +96,31 @@ impl Config {
 pub struct MiscSettings {
     #[default(false)]
     pub systemd_service: bool,
+
+    /// Where the content of tagged files is remembered, for rules with `remember_tags`.
+    #[default(default_index_path())]
+    #[serde(deserialize_with = "expand_path")]
+    pub index_path: PathBuf,
+
+    /// The socket the daemon serves its events on, for `filetags events`, e.g.
+    /// `$XDG_RUNTIME_DIR/filetags.sock`. Events aren't served unless it's set.
+    #[default(None)]
+    #[serde(deserialize_with = "expand_optional_path")]
+    pub event_socket: Option<PathBuf>,
+
+    /// How long a hook command may run before it's killed.
+    #[default(Age(Duration::from_secs(30)))]
+    pub hook_timeout: Age,
+
+    /// How many hook commands may run at once. The rest wait their turn.
+    #[default(4)]
+    pub max_concurrent_hooks: usize,
+
+    /// Whether to also load the files in the `conf.d` directory next to the main config. By
+    /// default, they're loaded only for the default config location, so that a config given with
+    /// `--config` doesn't pick up whatever lies next to it.
+    #[default(None)]
+    pub conf_d: Option<bool>,
 }
 
 // Rule ///////////////////////////////////////////////////////////////////////
@@ -46,18 +131,117 @@ pub struct Rule {
     #[default("rule")]
     pub name: String,
 
+    /// The names of templates (see `templates` in the config) to take the fields and settings
+    /// this rule leaves unset from. Later ones take precedence over earlier ones.
+    #[default(vec![])]
+    pub extends: Vec<String>,
+
+    /// The directories to watch, which may be globs like `/media/*/Samples` or
+    /// `~/Music/**/Samples`. Once the config is loaded, these are the directories they matched,
+    /// and more are added as matching directories appear (see `WorkerType::GlobWatchers`).
     #[default(vec![])]
     #[serde(deserialize_with = "expand_paths")]
     pub watch_dirs: Vec<PathBuf>,
 
+    /// The watch_dirs as configured, globs included.
+    #[default(vec![])]
+    #[serde(skip)]
+    pub watch_patterns: Vec<PathBuf>,
+
     #[default(vec![])]
     #[serde(deserialize_with = "expand_paths")]
     pub link_dirs: Vec<PathBuf>,
 
+    /// Playlist files listing every member, kept up to date like the link_dirs.
+    #[default(vec![])]
+    pub playlists: Vec<Playlist>,
+
     #[default(vec![])]
     #[serde(with = "serde_regex")]
     pub regex: Vec<Regex>,
 
+    /// The names of matchers registered in code to also match against, e.g. one looking files
+    /// up in a database (see `Config::register_matcher`).
+    #[default(vec![])]
+    pub matchers: Vec<String>,
+
+    /// A boolean expression over other rules, used instead of `regex`.
+    /// If no watch_dirs are given, the watch_dirs of the referenced rules are used.
+    #[default(None)]
+    pub query: Option<Query>,
+
+    /// Also match files carrying this tag in the xattr named by the `xattr_name` setting,
+    /// as an alternative to renaming them.
+    #[default(None)]
+    pub xattr_tag: Option<String>,
+
+    /// Also match files that have this tag declared in a sidecar file, either in a `.filetags`
+    /// file in their directory (`filename: tag1, tag2`) or in a `<file>.tags` next to them.
+    #[default(None)]
+    pub sidecar_tag: Option<String>,
+
+    /// The tag written to the xattrs of linked sources when the `write_xattr_tags` setting is
+    /// on. Defaults to the rule name. Can't be the same as `xattr_tag`.
+    #[default(None)]
+    pub write_tag: Option<String>,
+
+    /// The string prepended to a source's name to tag it, e.g. `_`, when a link to it is
+    /// dropped into a link_dir with `tag_on_link_drop`.
+    #[default(None)]
+    pub cookie: Option<String>,
+
+    /// The replacement for the part of a source's name matched by `regex` when untagging it,
+    /// e.g. after its link was deleted with `untag_on_link_delete`. Supports `$1`-style
+    /// captures. Defaults to stripping the match.
+    #[default(None)]
+    pub untag_replace: Option<String>,
+
+    /// Only match files at least this large, e.g. `100KB`.
+    #[default(None)]
+    pub min_size: Option<ByteSize>,
+
+    /// Only match files at most this large, e.g. `2MB`.
+    #[default(None)]
+    pub max_size: Option<ByteSize>,
+
+    /// Only match files modified less than this long ago, e.g. `30d`.
+    #[default(None)]
+    pub newer_than: Option<Age>,
+
+    /// Only match files modified more than this long ago, e.g. `1year`.
+    #[default(None)]
+    pub older_than: Option<Age>,
+
+    /// Only match files with one of these extensions (case-insensitive), e.g. `[wav, flac]`.
+    #[default(vec![])]
+    pub extensions: Vec<String>,
+
+    /// Only match files whose MIME type, detected from magic bytes, matches, e.g. `audio/*`.
+    #[default(None)]
+    pub mime: Option<MimePattern>,
+
+    /// A Rhai predicate run for paths that are otherwise members, which may also choose the
+    /// name they're linked under, see `Script`. A rule with neither `regex`, `matchers`,
+    /// `xattr_tag` nor `sidecar_tag` leaves membership to its script alone.
+    #[default(None)]
+    pub script: Option<Script>,
+
+    /// A shell command run after a link to a member is created.
+    /// It gets `FILETAGS_EVENT`, `FILETAGS_RULE`, `FILETAGS_SOURCE` and `FILETAGS_LINK` in its
+    /// environment.
+    #[default(None)]
+    pub on_link: Option<String>,
+
+    /// A shell command run after a link is deleted, with the same environment as `on_link`.
+    #[default(None)]
+    pub on_unlink: Option<String>,
+
+    /// A shell command run when a link or source can't be given its name because it's taken.
+    /// The taken path is in `FILETAGS_CONFLICT`, and the link created instead, if any, in
+    /// `FILETAGS_LINK`.
+    #[default(None)]
+    pub on_conflict: Option<String>,
+
     #[serde(rename = "settings")]
     pub raw_settings: Option<RawRuleSettings>,
 
@@ -65,6 +249,81 @@ pub struct Rule {
     pub settings: RuleSettings,
 }
 
+impl Rule {
+    /// Returns whether any of the file-attribute predicates are set.
+    pub fn has_predicates(&self) -> bool {
+        self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.newer_than.is_some()
+            || self.older_than.is_some()
+            || !self.extensions.is_empty()
+            || self.mime.is_some()
+    }
+
+    /// Returns whether anything tags paths for the rule besides its script.
+    pub fn has_tag_sources(&self) -> bool {
+        !self.regex.is_empty()
+            || !self.matchers.is_empty()
+            || self.xattr_tag.is_some()
+            || self.sidecar_tag.is_some()
+    }
+
+    /// Returns whether any of the hook commands are set.
+    pub fn has_hooks(&self) -> bool {
+        self.on_link.is_some() || self.on_unlink.is_some() || self.on_conflict.is_some()
+    }
+
+    /// Returns whether any of the watch_dirs are globs.
+    pub fn has_watch_globs(&self) -> bool {
+        self.watch_patterns
+            .iter()
+            .any(|pattern| utils::is_glob(pattern))
+    }
+
+    /// Returns what the watch_dirs as configured match right now, in order.
+    pub fn expand_watch_patterns(&self) -> Vec<PathBuf> {
+        let mut dirs = vec![];
+        for pattern in &self.watch_patterns {
+            let found = match utils::is_glob(pattern) {
+                true => utils::glob_dirs(pattern),
+                false => vec![pattern.clone()],
+            };
+            for dir in found {
+                if !dirs.contains(&dir) {
+                    dirs.push(dir);
+                }
+            }
+        }
+        dirs
+    }
+
+    /// Returns whether the watch_dir was matched by a glob, rather than configured as is.
+    pub fn watch_dir_is_globbed(&self, watch_idx: usize) -> bool {
+        self.has_watch_globs() && !self.watch_patterns.contains(&self.watch_dirs[watch_idx])
+    }
+
+    /// Adds a directory matched by a glob to the watch_dirs, unless it's there already, and
+    /// returns its index. Directories are never removed, so that indices stay valid.
+    pub fn add_watch_dir(&mut self, dir: &Path) -> usize {
+        match self
+            .watch_dirs
+            .iter()
+            .position(|watch_dir| watch_dir == dir)
+        {
+            Some(watch_idx) => watch_idx,
+            None => {
+                self.watch_dirs.push(dir.to_path_buf());
+                self.watch_dirs.len() - 1
+            }
+        }
+    }
+
+    /// The tag written to the xattrs of linked sources, see `write_tag`.
+    pub fn write_tag(&self) -> &str {
+        self.write_tag.as_deref().unwrap_or(&self.name)
+    }
+}
+
 // RuleSettings ///////////////////////////////////////////////////////////////
 
 #[derive(SmartDefault, Debug, Clone, Deserialize, Serialize)]
@@ -85,6 +344,114 @@ pub struct RuleSettings {
 
     #[default(Some(10))]
     pub clean_interval: Option<u32>,
+
+    #[default(Targets::Files)]
+    pub targets: Targets,
+
+    #[default(false)]
+    pub link_inside_tagged_dirs: bool,
+
+    #[default(false)]
+    pub inherit_from_parents: bool,
+
+    #[default(MatchOn::Basename)]
+    pub match_on: MatchOn,
+
+    #[default("user.xdg.tags")]
+    pub xattr_name: String,
+
+    #[default(false)]
+    pub write_xattr_tags: bool,
+
+    #[default(false)]
+    pub untag_on_link_delete: bool,
+
+    #[default(false)]
+    pub tag_on_link_drop: bool,
+
+    #[default(false)]
+    pub propagate_link_renames: bool,
+
+    #[default(false)]
+    pub remember_tags: bool,
+
+    #[default(RecallPolicy::Offer)]
+    pub recall_policy: RecallPolicy,
+
+    #[default(None)]
+    pub dedupe: Option<Dedupe>,
+}
+
+impl RuleSettings {
+    /// Returns whether the rule's link_dirs need to be watched for changes made by the user.
+    pub fn watches_link_dirs(&self) -> bool {
+        self.untag_on_link_delete || self.tag_on_link_drop || self.propagate_link_renames
+    }
+}
+
+/// Which kinds of files a rule creates symlinks to.
+/// A tagged directory is linked as a single symlink.
+#[derive(SmartDefault, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Targets {
+    #[default]
+    Files,
+    Dirs,
+    Both,
+}
+
+impl Targets {
+    pub fn includes_files(&self) -> bool {
+        matches!(self, Targets::Files | Targets::Both)
+    }
+
+    pub fn includes_dirs(&self) -> bool {
+        matches!(self, Targets::Dirs | Targets::Both)
+    }
+}
+
+/// Which part of a path a rule's regexes are matched against.
+#[derive(SmartDefault, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchOn {
+    /// The filename, e.g. `_kick.wav`.
+    #[default]
+    Basename,
+    /// The filename without its extension, e.g. `_kick`.
+    Stem,
+    /// The extension without the leading dot, e.g. `wav`.
+    Extension,
+    /// The path relative to its watch_dir, e.g. `drums/one-shots/_kick.wav`.
+    RelativePath,
+    /// The full path, e.g. `/home/user/Samples/drums/one-shots/_kick.wav`.
+    AbsolutePath,
+}
+
+/// What to do when an untagged file appears with the same content as a file that was tagged
+/// for a rule with `remember_tags`.
+#[derive(SmartDefault, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecallPolicy {
+    /// Log it, so the tag can be applied with `filetags index apply`.
+    #[default]
+    Offer,
+    /// Tag it, like `tag_on_link_drop` does.
+    Rename,
+    /// Link it as is, without tagging it.
+    Link,
+}
+
+/// What to do when a source has the same content as a source the rule already links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dedupe {
+    /// Keep the link that was created first, and don't link the duplicate. Within a scan, the
+    /// copy whose path sorts first is linked first.
+    KeepFirst,
+    /// Keep the link to the source with the shortest path, replacing links to longer ones.
+    KeepShortestPath,
+    /// Link it anyway, but log the duplicate.
+    Report,
 }
 
 // Config - Deserialization ///////////////////////////////////////////////////
@@ -96,6 +463,12 @@ pub struct RawConfig {
     pub rules: Vec<Rule>,
     #[serde(rename = "default_settings")]
     pub default_rule_settings: RuleSettings,
+    /// Rules that others can extend, but that don't link anything themselves.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub templates: Vec<Rule>,
+    /// Other config files to load, see `Config::load`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
 }
 
 #[derive(SmartDefault, Debug, Clone, Deserialize, Serialize)]
@@ -110,64 +483,163 @@ pub struct RawRuleSettings {
     pub max_depth: Option<u32>,
     pub follow_symlinks: Option<bool>,
     pub clean_interval: Option<Option<u32>>,
+    pub targets: Option<Targets>,
+    pub link_inside_tagged_dirs: Option<bool>,
+    pub inherit_from_parents: Option<bool>,
+    pub match_on: Option<MatchOn>,
+    pub xattr_name: Option<String>,
+    pub write_xattr_tags: Option<bool>,
+    pub untag_on_link_delete: Option<bool>,
+    pub tag_on_link_drop: Option<bool>,
+    pub propagate_link_renames: Option<bool>,
+    pub remember_tags: Option<bool>,
+    pub recall_policy: Option<RecallPolicy>,
+    pub dedupe: Option<Option<Dedupe>>,
 }
 
-macro_rules! unwrap_raw_setting_or_default {
-    ($field:ident, $raw_rule_settings:ident, $raw_config:ident) => {{
-        $raw_rule_settings
-            .$field
-            .unwrap_or($raw_config.default_rule_settings.$field.clone())
-    }};
-}
-
-macro_rules! new_rule_settings_with_defaults {
-    ( $raw_config:ident, $raw_rule_settings:ident, ($($field:ident),+) ) => {{
-        RuleSettings {
-            $(
-                $field: unwrap_raw_setting_or_default!($field, $raw_rule_settings, $raw_config),
-            )+
+impl Config {
+    /// Resolves each rule in layers: its own fields and settings, then those of the templates
+    /// it extends, then the default settings. Then validates the rules and resolves query
+    /// rules. Used both for config files and `ConfigBuilder`.
+    pub fn from_raw(raw_config: RawConfig) -> Result<Self> {
+        if !raw_config.include.is_empty() {
+            return Err(Error::Config(
+                "`include` can only be used in config files".to_string(),
+            ));
         }
-    }};
-}
-
-impl<'de> Deserialize<'de> for Config {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let raw_config = RawConfig::deserialize(deserializer)?;
-
-        let updated_rules = raw_config
-            .rules
+        let mut rules = raw_config.rules.clone();
+        template::resolve_templates(&mut rules, &raw_config.templates)
+            .map_err(|e| Error::Config(format!("{:#}", e)))?;
+        let mut updated_rules = rules
             .iter()
             .map(|rule| -> Rule {
                 let mut rule_new = rule.clone();
                 rule_new.settings = {
-                    let raw_rule_settings = rule.raw_settings.clone().unwrap_or_default();
-                    new_rule_settings_with_defaults!(
-                        raw_config,
-                        raw_rule_settings,
-                        (
+                    let mut settings = raw_config.default_rule_settings.clone();
+                    macro_rules! set_field {
+                        ($field:ident) => {
+                            if let Some(value) = $field {
+                                settings.$field = value;
+                            }
+                        };
+                    }
+                    for_each_field!(
+                        set_field,
+                        rule.raw_settings.clone().unwrap_or_default() => RawRuleSettings {
                             create_missing_dirs,
                             exclude_pattern,
                             max_depth,
                             follow_symlinks,
-                            clean_interval
-                        )
-                    )
+                            clean_interval,
+                            targets,
+                            link_inside_tagged_dirs,
+                            inherit_from_parents,
+                            match_on,
+                            xattr_name,
+                            write_xattr_tags,
+                            untag_on_link_delete,
+                            tag_on_link_drop,
+                            propagate_link_renames,
+                            remember_tags,
+                            recall_policy,
+                            dedupe,
+                        }
+                    );
+                    settings
                 };
                 rule_new.raw_settings = None;
                 rule_new
             })
             .collect::<Vec<Rule>>();
 
+        validate_rules(&updated_rules)?;
+        query::resolve_query_rules(&mut updated_rules)
+            .map_err(|e| Error::Config(format!("{:#}", e)))?;
+        // query rules have inherited the globs of the rules they reference by now
+        for rule in &mut updated_rules {
+            rule.watch_patterns = mem::take(&mut rule.watch_dirs);
+            rule.watch_dirs = rule.expand_watch_patterns();
+        }
+
         Ok(Config {
             misc: raw_config.misc,
             rules: updated_rules,
+            matchers: Matchers::default(),
+            sources: vec![],
         })
     }
 }
 
+impl<'de> Deserialize<'de> for Config {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw_config = RawConfig::deserialize(deserializer)?;
+        Config::from_raw(raw_config).map_err(|e| match e {
+            Error::Config(message) => D::Error::custom(message),
+            e => D::Error::custom(e),
+        })
+    }
+}
+
+/// Checks what the types of the rules can't: that their names are unique and non-empty, and
+/// that their predicates can match at all.
+fn validate_rules(rules: &[Rule]) -> Result<()> {
+    let mut names = HashSet::new();
+    for rule in rules {
+        if rule.name.is_empty() {
+            return Err(Error::Config("a rule has an empty name".to_string()));
+        }
+        if !names.insert(&rule.name) {
+            return Err(Error::Config(format!(
+                "more than one rule is named {:?}",
+                rule.name
+            )));
+        }
+        for pattern in rule.watch_dirs.iter().filter(|p| utils::is_glob(p)) {
+            if let Err(e) = glob::Pattern::new(&pattern.to_string_lossy()) {
+                return Err(Error::Config(format!(
+                    "rule {:?}: watch_dir {:?}: {}",
+                    rule.name, pattern, e
+                )));
+            }
+        }
+        // dropped links would be deleted without their sources becoming members
+        if rule.settings.tag_on_link_drop && rule.cookie.is_none() && rule.xattr_tag.is_none() {
+            return Err(Error::Config(format!(
+                "rule {:?}: `tag_on_link_drop` needs `cookie` or `xattr_tag` to tag with",
+                rule.name
+            )));
+        }
+        // the written tag would make every linked source a member for good
+        if rule.settings.write_xattr_tags && rule.xattr_tag.as_deref() == Some(rule.write_tag()) {
+            return Err(Error::Config(format!(
+                "rule {:?}: `write_tag` can't be the same as `xattr_tag` when `write_xattr_tags` \
+                 is set",
+                rule.name
+            )));
+        }
+        // a relative path would depend on where the daemon was started
+        if let Some(playlist) = rule.playlists.iter().find(|p| !p.path.is_absolute()) {
+            return Err(Error::Config(format!(
+                "rule {:?}: playlist `path` {:?} isn't absolute",
+                rule.name, playlist.path
+            )));
+        }
+        if let (Some(min_size), Some(max_size)) = (rule.min_size, rule.max_size) {
+            if min_size > max_size {
+                return Err(Error::Config(format!(
+                    "rule {:?}: `min_size` is larger than `max_size`",
+                    rule.name
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
 // Deserializer shell expansions //////////////////////////////////////////////
 
 /// Trait Extension for PathBuf for shell expansions.
@@ -185,6 +657,26 @@ impl PathBufExpand for PathBuf {
     }
 }
 
+/// a custom deserializer for `PathBuf` to expand tildes and variables.
+fn expand_path<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    PathBuf::deserialize(deserializer)?
+        .shell_expand()
+        .map_err(D::Error::custom)
+}
+
+/// a custom deserializer for `Option<PathBuf>` to expand tildes and variables.
+fn expand_optional_path<'de, D>(deserializer: D) -> Result<Option<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<PathBuf>::deserialize(deserializer)?
+        .map(|p| p.shell_expand().map_err(D::Error::custom))
+        .transpose()
+}
+
 /// a custom deserializer for `Vec<PathBuf>` to expand tildes and variables.
 fn expand_paths<'de, D>(deserializer: D) -> Result<Vec<PathBuf>, D::Error>
 where
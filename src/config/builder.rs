@@ -0,0 +1,238 @@
+use std::{path::PathBuf, sync::Arc};
+
+use regex::Regex;
+
+use super::{
+    Age, ByteSize, Config, MimePattern, MiscSettings, Playlist, RawConfig, RawRuleSettings, Rule,
+    RuleSettings,
+};
+use crate::{Error, Matcher, Matchers, Result};
+
+// ConfigBuilder //////////////////////////////////////////////////////////////
+
+/// Builds a `Config` in code, for embedding filetags without writing a config file.
+///
+/// Rule settings are merged with the default settings, and the rules validated, the same way
+/// as for a config file (see `Config::from_raw`).
+///
+/// ```no_run
+/// use filetags::{Config, Rule};
+///
+/// let config = Config::builder()
+///     .rule(
+///         Rule::builder("fav")
+///             .watch_dir("/home/user/Samples")
+///             .link_dir("/home/user/Favorites")
+///             .regex("^_"),
+///     )
+///     .build()?;
+/// # Ok::<(), filetags::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    misc: MiscSettings,
+    default_settings: RuleSettings,
+    rules: Vec<RuleBuilder>,
+    templates: Vec<RuleBuilder>,
+    matchers: Matchers,
+}
+
+impl ConfigBuilder {
+    pub fn misc(mut self, misc: MiscSettings) -> Self {
+        self.misc = misc;
+        self
+    }
+
+    /// The settings of rules that don't override them, like `default_settings` in a config file.
+    pub fn default_settings(mut self, settings: RuleSettings) -> Self {
+        self.default_settings = settings;
+        self
+    }
+
+    pub fn rule(mut self, rule: RuleBuilder) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// A rule that others can extend by its name, see `RuleBuilder::extends`.
+    pub fn template(mut self, template: RuleBuilder) -> Self {
+        self.templates.push(template);
+        self
+    }
+
+    /// Registers a matcher that rules can refer to by name, see `RuleBuilder::matcher`.
+    pub fn matcher(mut self, name: impl Into<String>, matcher: impl Matcher + 'static) -> Self {
+        self.matchers.insert(name, matcher);
+        self
+    }
+
+    pub fn build(self) -> Result<Arc<Config>> {
+        let rules = self
+            .rules
+            .into_iter()
+            .map(RuleBuilder::build)
+            .collect::<Result<Vec<_>>>()?;
+        let templates = self
+            .templates
+            .into_iter()
+            .map(RuleBuilder::build)
+            .collect::<Result<Vec<_>>>()?;
+        let mut config = Config::from_raw(RawConfig {
+            misc: self.misc,
+            rules,
+            default_rule_settings: self.default_settings,
+            templates,
+            include: vec![],
+        })?;
+        config.matchers = self.matchers;
+        config.check_matchers()?;
+
+        Ok(Arc::new(config))
+    }
+}
+
+// RuleBuilder ////////////////////////////////////////////////////////////////
+
+/// Builds a `Rule` in code, see `ConfigBuilder`.
+/// Regexes and queries are given as strings, and checked by `build`.
+#[derive(Debug, Clone)]
+pub struct RuleBuilder {
+    rule: Rule,
+    regex: Vec<String>,
+    query: Option<String>,
+    script: Option<String>,
+    settings: RawRuleSettings,
+}
+
+/// Generates setters for the optional fields of `Rule`.
+macro_rules! optional_rule_setters {
+    ($( $(#[$doc:meta])* $field:ident: $ty:ty ),+ $(,)?) => {
+        $(
+            $(#[$doc])*
+            pub fn $field(mut self, $field: impl Into<$ty>) -> Self {
+                self.rule.$field = Some($field.into());
+                self
+            }
+        )+
+    };
+}
+
+impl RuleBuilder {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            rule: Rule {
+                name: name.into(),
+                ..Rule::default()
+            },
+            regex: vec![],
+            query: None,
+            script: None,
+            settings: RawRuleSettings::default(),
+        }
+    }
+
+    /// Take the fields and settings this rule leaves unset from a template added with
+    /// `ConfigBuilder::template`. Can be given more than once, later ones taking precedence.
+    pub fn extends(mut self, template: impl Into<String>) -> Self {
+        self.rule.extends.push(template.into());
+        self
+    }
+
+    pub fn watch_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.rule.watch_dirs.push(path.into());
+        self
+    }
+
+    pub fn link_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.rule.link_dirs.push(path.into());
+        self
+    }
+
+    pub fn playlist(mut self, playlist: Playlist) -> Self {
+        self.rule.playlists.push(playlist);
+        self
+    }
+
+    pub fn regex(mut self, pattern: impl Into<String>) -> Self {
+        self.regex.push(pattern.into());
+        self
+    }
+
+    /// Also match against a matcher registered with `ConfigBuilder::matcher`. Can be given more
+    /// than once.
+    pub fn matcher(mut self, name: impl Into<String>) -> Self {
+        self.rule.matchers.push(name.into());
+        self
+    }
+
+    /// A boolean expression over other rules, e.g. `fav AND drums`, used instead of `regex`.
+    pub fn query(mut self, query: impl Into<String>) -> Self {
+        self.query = Some(query.into());
+        self
+    }
+
+    /// A Rhai predicate for paths that are otherwise members, see `Script`.
+    pub fn script(mut self, source: impl Into<String>) -> Self {
+        self.script = Some(source.into());
+        self
+    }
+
+    /// Only match files with this extension (case-insensitive). Can be given more than once.
+    pub fn extension(mut self, extension: impl Into<String>) -> Self {
+        self.rule.extensions.push(extension.into());
+        self
+    }
+
+    optional_rule_setters!(
+        xattr_tag: String,
+        sidecar_tag: String,
+        write_tag: String,
+        cookie: String,
+        untag_replace: String,
+        min_size: ByteSize,
+        max_size: ByteSize,
+        newer_than: Age,
+        older_than: Age,
+        mime: MimePattern,
+        on_link: String,
+        on_unlink: String,
+        on_conflict: String,
+    );
+
+    /// Settings that override the default settings, like `settings` in a config file.
+    pub fn settings(mut self, settings: RawRuleSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Compiles the regexes and the script, and parses the query. The rule's settings are merged
+    /// with the default settings once the whole config is built.
+    pub fn build(self) -> Result<Rule> {
+        let mut rule = self.rule;
+        rule.regex = self
+            .regex
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<_, _>>()?;
+        rule.query = self
+            .query
+            .map(|query| query.parse())
+            .transpose()
+            .map_err(|e| Error::Config(format!("rule {:?}: {:#}", rule.name, e)))?;
+        rule.script = self
+            .script
+            .map(|source| source.parse())
+            .transpose()
+            .map_err(|e| Error::Config(format!("rule {:?}: {:#}", rule.name, e)))?;
+        rule.raw_settings = Some(self.settings);
+
+        Ok(rule)
+    }
+}
+
+impl Rule {
+    /// Creates a rule in code, see `ConfigBuilder`.
+    pub fn builder(name: impl Into<String>) -> RuleBuilder {
+        RuleBuilder::new(name)
+    }
+}
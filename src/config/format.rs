@@ -0,0 +1,61 @@
+use std::{fmt, path::Path};
+
+use serde::{de::DeserializeOwned, Serialize};
+use smart_default::SmartDefault;
+
+use crate::{Error, Result};
+
+// ConfigFormat ///////////////////////////////////////////////////////////////
+
+/// The file formats a config can be written in. They all describe the same `RawConfig`.
+#[derive(SmartDefault, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConfigFormat {
+    #[default]
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFormat::Yaml => write!(f, "YAML"),
+            ConfigFormat::Toml => write!(f, "TOML"),
+            ConfigFormat::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+impl ConfigFormat {
+    /// Guesses the format from a file's extension, if it's a known one.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "yml" | "yaml" => Some(ConfigFormat::Yaml),
+            "toml" => Some(ConfigFormat::Toml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+
+    /// Parses a config, or anything else written in the format, e.g. a `serde_json::Value`.
+    pub fn parse<T: DeserializeOwned>(self, contents: &str) -> Result<T> {
+        let parsed = match self {
+            ConfigFormat::Yaml => serde_yml::from_str(contents).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::from_str(contents).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::from_str(contents).map_err(|e| e.to_string()),
+        };
+        parsed.map_err(|e| Error::Config(format!("parsing {}: {}", self, e.trim_end())))
+    }
+
+    /// Writes a config, or anything else, in the format.
+    pub fn to_string<T: Serialize>(self, value: &T) -> Result<String> {
+        let written = match self {
+            ConfigFormat::Yaml => serde_yml::to_string(value).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::to_string_pretty(value).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::to_string_pretty(value)
+                .map(|json| json + "\n")
+                .map_err(|e| e.to_string()),
+        };
+        written.map_err(|e| Error::Config(format!("writing {}: {}", self, e)))
+    }
+}
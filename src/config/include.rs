@@ -0,0 +1,245 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{Map, Value};
+
+use super::{Config, ConfigFormat, RawConfig};
+use crate::{args::default_config_path, is_glob, Error, Result};
+
+/// The directory next to the main config file whose files are loaded after it.
+const CONF_D: &str = "conf.d";
+
+// Config loading /////////////////////////////////////////////////////////////
+
+impl Config {
+    /// Loads a config file, the files it includes, and the files in the `conf.d` directory next
+    /// to it, in that order. `conf.d` is only loaded for the default config location, unless
+    /// `misc.conf_d` says otherwise.
+    ///
+    /// `include` takes paths or globs, relative to the including file, which may include others
+    /// in turn. Rules and templates are concatenated, and their names must be unique across
+    /// files. Only the main file may set `misc`. Its `default_settings` apply to every rule,
+    /// while those of another file are layered on top of them for its own rules and the files
+    /// it includes.
+    ///
+    /// Each file is in the format given by its extension, or YAML. `format` overrides that for
+    /// the main file.
+    pub fn load(path: &Path, format: Option<ConfigFormat>) -> Result<Self> {
+        let mut loader = Loader::default();
+        let format = format
+            .or_else(|| ConfigFormat::from_path(path))
+            .unwrap_or_default();
+        loader.load_file(path, format, None)?;
+
+        let load_conf_d = loader
+            .merged
+            .get("misc")
+            .and_then(|misc| misc.get("conf_d"))
+            .and_then(Value::as_bool)
+            .unwrap_or_else(|| path == default_config_path());
+        if let Some(conf_d) = path
+            .parent()
+            .filter(|_| load_conf_d)
+            .map(|dir| dir.join(CONF_D))
+        {
+            let mut paths = match fs::read_dir(&conf_d) {
+                Ok(entries) => entries
+                    .filter_map(|entry| entry.ok().map(|e| e.path()))
+                    .filter(|p| p.is_file() && ConfigFormat::from_path(p).is_some())
+                    .collect::<Vec<_>>(),
+                Err(_) => vec![],
+            };
+            paths.sort();
+            for path in paths {
+                let format = ConfigFormat::from_path(&path).unwrap_or_default();
+                loader.load_file(&path, format, Some(&Map::new()))?;
+            }
+        }
+
+        let mut config: Config = serde_json::from_value(Value::Object(loader.merged))
+            .map_err(|e| Error::Config(e.to_string()))?;
+        config.sources = loader.sources;
+
+        Ok(config)
+    }
+}
+
+/// Merges config files as they're loaded, see `Config::load`.
+#[derive(Debug, Default)]
+struct Loader {
+    /// The merged config, as written.
+    merged: Map<String, Value>,
+    /// The file each rule and template was defined in, by kind and name.
+    names: HashMap<(&'static str, String), PathBuf>,
+    /// Every file loaded, canonicalized, to catch files included twice.
+    seen: HashSet<PathBuf>,
+    sources: Vec<PathBuf>,
+}
+
+impl Loader {
+    /// Loads a file and the files it includes. `layered_defaults` is None for the main file,
+    /// and otherwise the default settings layered by the files that included this one.
+    fn load_file(
+        &mut self,
+        path: &Path,
+        format: ConfigFormat,
+        layered_defaults: Option<&Map<String, Value>>,
+    ) -> Result<()> {
+        let canonical = fs::canonicalize(path).map_err(|source| Error::Io {
+            context: format!("reading config file {:?}", path),
+            source,
+        })?;
+        if !self.seen.insert(canonical) {
+            return Err(file_error(path, "it's included more than once"));
+        }
+        self.sources.push(path.to_path_buf());
+
+        let contents = fs::read_to_string(path).map_err(|source| Error::Io {
+            context: format!("reading config file {:?}", path),
+            source,
+        })?;
+        // check each file on its own, so that errors point to it
+        format
+            .parse::<RawConfig>(&contents)
+            .map_err(|e| attribute_error(path, e))?;
+        let mut file = match format
+            .parse(&contents)
+            .map_err(|e| attribute_error(path, e))?
+        {
+            Value::Object(file) => file,
+            _ => Map::new(),
+        };
+
+        let own_defaults = match file.remove("default_settings") {
+            Some(Value::Object(defaults)) => defaults,
+            _ => Map::new(),
+        };
+        let defaults = match layered_defaults {
+            None => {
+                if let Some(misc) = file.remove("misc") {
+                    self.merged.insert("misc".to_string(), misc);
+                }
+                self.merged
+                    .insert("default_settings".to_string(), own_defaults.into());
+                Map::new()
+            }
+            Some(_) if file.contains_key("misc") => {
+                return Err(file_error(
+                    path,
+                    "`misc` can only be set in the main config",
+                ))
+            }
+            Some(layered) => {
+                let mut defaults = layered.clone();
+                defaults.extend(own_defaults);
+                defaults
+            }
+        };
+
+        for template in take_array(&mut file, "templates") {
+            self.check_name("template", &template, path)?;
+            push(&mut self.merged, "templates", template);
+        }
+        let rules = take_array(&mut file, "rules");
+        // a file's defaults are a template its rules implicitly extend first, so that they
+        // take precedence over the main file's, but not over the templates they extend
+        let defaults_template = format!("{} default_settings", path.display());
+        if !defaults.is_empty() && !rules.is_empty() {
+            let template = serde_json::json!({
+                "name": defaults_template,
+                "settings": defaults,
+            });
+            self.check_name("template", &template, path)?;
+            push(&mut self.merged, "templates", template);
+        }
+        for mut rule in rules {
+            self.check_name("rule", &rule, path)?;
+            if let (false, Value::Object(rule)) = (defaults.is_empty(), &mut rule) {
+                let mut extends = vec![Value::from(defaults_template.as_str())];
+                if let Some(Value::Array(own_extends)) = rule.remove("extends") {
+                    extends.extend(own_extends);
+                }
+                rule.insert("extends".to_string(), extends.into());
+            }
+            push(&mut self.merged, "rules", rule);
+        }
+
+        let includes = take_array(&mut file, "include");
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for pattern in includes.iter().filter_map(Value::as_str) {
+            for included in resolve_include(dir, pattern).map_err(|e| file_error(path, &e))? {
+                let format = ConfigFormat::from_path(&included).unwrap_or_default();
+                self.load_file(&included, format, Some(&defaults))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that a rule or template is the only one with its name.
+    fn check_name(&mut self, kind: &'static str, entry: &Value, path: &Path) -> Result<()> {
+        let name = entry["name"].as_str().unwrap_or("rule").to_string();
+        if let Some(defined_in) = self.names.get(&(kind, name.clone())) {
+            return Err(file_error(
+                path,
+                &format!("{} {:?} is already defined in {:?}", kind, name, defined_in),
+            ));
+        }
+        self.names.insert((kind, name), path.to_path_buf());
+
+        Ok(())
+    }
+}
+
+fn take_array(file: &mut Map<String, Value>, key: &str) -> Vec<Value> {
+    match file.remove(key) {
+        Some(Value::Array(values)) => values,
+        _ => vec![],
+    }
+}
+
+fn push(merged: &mut Map<String, Value>, key: &str, value: Value) {
+    match merged.entry(key).or_insert_with(|| Value::Array(vec![])) {
+        Value::Array(values) => values.push(value),
+        other => *other = Value::Array(vec![value]),
+    }
+}
+
+/// Returns the files an `include` entry refers to, sorted. Paths are relative to `dir`.
+/// A glob may match nothing, but a plain path must exist.
+fn resolve_include(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let expanded = shellexpand::full(pattern)
+        .map_err(|e| format!("expanding include {:?}: {}", pattern, e))?;
+    let full = dir.join(expanded.as_ref());
+    let full = full.to_string_lossy();
+    if !is_glob(Path::new(full.as_ref())) {
+        return match Path::new(full.as_ref()).is_file() {
+            true => Ok(vec![PathBuf::from(full.as_ref())]),
+            false => Err(format!("included file {:?} doesn't exist", full)),
+        };
+    }
+
+    let mut paths = glob::glob(&full)
+        .map_err(|e| format!("include {:?}: {}", pattern, e))?
+        .filter_map(|entry| entry.ok())
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    Ok(paths)
+}
+
+fn file_error(path: &Path, message: &str) -> Error {
+    Error::Config(format!("{}: {}", path.display(), message))
+}
+
+/// Prefixes a config error with the file it's in.
+fn attribute_error(path: &Path, e: Error) -> Error {
+    match e {
+        Error::Config(message) => file_error(path, &message),
+        e => e,
+    }
+}
@@ -0,0 +1,62 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use smart_default::SmartDefault;
+
+use super::expand_path;
+
+// Playlist ///////////////////////////////////////////////////////////////////
+
+/// A playlist file listing every member of a rule, as an alternative (or addition) to a
+/// link_dir, e.g. for media players and DAWs that read playlists better than folders.
+#[derive(SmartDefault, Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Playlist {
+    /// Required, and must be absolute once `~` and variables are expanded.
+    #[serde(deserialize_with = "expand_path")]
+    pub path: PathBuf,
+
+    /// Defaults to XSPF for a `.xspf` path, and M3U8 otherwise.
+    #[default(None)]
+    #[serde(default)]
+    pub format: Option<PlaylistFormat>,
+
+    /// Write paths relative to the playlist's directory, instead of absolute ones.
+    #[default(false)]
+    #[serde(default)]
+    pub relative_paths: bool,
+
+    #[default(PlaylistSort::Path)]
+    #[serde(default)]
+    pub sort: PlaylistSort,
+}
+
+impl Playlist {
+    pub fn format(&self) -> PlaylistFormat {
+        self.format
+            .unwrap_or_else(|| match self.path.extension().and_then(|e| e.to_str()) {
+                Some(e) if e.eq_ignore_ascii_case("xspf") => PlaylistFormat::Xspf,
+                _ => PlaylistFormat::M3u8,
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistFormat {
+    M3u8,
+    Xspf,
+}
+
+/// The order of the entries of a playlist.
+#[derive(SmartDefault, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistSort {
+    /// By full path.
+    #[default]
+    Path,
+    /// By filename, then full path.
+    Name,
+    /// Most recently modified first.
+    Modified,
+}
@@ -0,0 +1,123 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+// ByteSize ///////////////////////////////////////////////////////////////////
+
+/// A file size in bytes, written either as a plain number or with a unit suffix,
+/// e.g. `512`, `100KB`, `2MB`, `1.5GiB`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: f64 = number
+            .parse()
+            .map_err(|e| anyhow::anyhow!("parsing size {:?}: {}", s, e))?;
+        let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kb" => 1000,
+            "m" | "mb" => 1000_u64.pow(2),
+            "g" | "gb" => 1000_u64.pow(3),
+            "ki" | "kib" => 1 << 10,
+            "mi" | "mib" => 1 << 20,
+            "gi" | "gib" => 1 << 30,
+            other => anyhow::bail!("unknown size unit {:?} in {:?}", other, s),
+        };
+        Ok(Self((number * multiplier as f64) as u64))
+    }
+}
+
+impl Serialize for ByteSize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u64(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bytes(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Bytes(bytes) => Ok(Self(bytes)),
+            Raw::Text(s) => s.parse().map_err(|e| D::Error::custom(format!("{:#}", e))),
+        }
+    }
+}
+
+// Age ////////////////////////////////////////////////////////////////////////
+
+/// A duration compared against a file's mtime, e.g. `30d`, `12h`, `1week`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Age(pub Duration);
+
+impl FromStr for Age {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        humantime::parse_duration(s.trim())
+            .map(Self)
+            .map_err(|e| anyhow::anyhow!("parsing duration {:?}: {}", s, e))
+    }
+}
+
+impl fmt::Display for Age {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", humantime::format_duration(self.0))
+    }
+}
+
+impl Serialize for Age {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Age {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|e| D::Error::custom(format!("{:#}", e)))
+    }
+}
+
+// MimePattern ////////////////////////////////////////////////////////////////
+
+/// Matches a MIME type, either exactly (`audio/x-wav`) or by its top-level type (`audio/*`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MimePattern(pub String);
+
+impl MimePattern {
+    pub fn matches(&self, mime_type: &str) -> bool {
+        match self.0.strip_suffix("/*") {
+            Some(top_level) => mime_type
+                .split_once('/')
+                .is_some_and(|(t, _)| t.eq_ignore_ascii_case(top_level)),
+            None => self.0 == "*" || self.0.eq_ignore_ascii_case(mime_type),
+        }
+    }
+}
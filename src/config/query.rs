@@ -0,0 +1,418 @@
+use std::{collections::HashMap, fmt, path::PathBuf, str::FromStr};
+
+use anyhow::Context;
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Rule;
+
+// Query //////////////////////////////////////////////////////////////////////
+
+/// A boolean expression over the membership of other rules.
+///
+/// Examples:
+/// - `fav AND drums AND NOT loops`
+/// - `any of kick, snare`
+/// - `"favorite samples" AND (all(drums, acoustic) OR NOT loops)`
+///
+/// Rule names containing whitespace or reserved characters must be double-quoted.
+#[derive(Debug, Clone)]
+pub struct Query {
+    source: String,
+    expr: Expr,
+}
+
+/// A parsed `Query` expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Rule(String),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+impl Query {
+    /// The original, unparsed query string.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    /// Returns the names of every rule referenced in the query.
+    pub fn rule_names(&self) -> Vec<&str> {
+        let mut names = vec![];
+        self.expr.collect_rule_names(&mut names);
+        names
+    }
+
+    /// Evaluates the query, using `is_member` to look up whether the path being checked is a
+    /// member of the referenced rule.
+    pub fn eval<F>(&self, is_member: &mut F) -> anyhow::Result<bool>
+    where
+        F: FnMut(&str) -> anyhow::Result<bool>,
+    {
+        self.expr.eval(is_member)
+    }
+}
+
+impl Expr {
+    fn collect_rule_names<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            Expr::Rule(name) => {
+                if !names.contains(&name.as_str()) {
+                    names.push(name)
+                }
+            }
+            Expr::Not(inner) => inner.collect_rule_names(names),
+            Expr::And(exprs) | Expr::Or(exprs) => {
+                exprs.iter().for_each(|e| e.collect_rule_names(names))
+            }
+        }
+    }
+
+    fn eval<F>(&self, is_member: &mut F) -> anyhow::Result<bool>
+    where
+        F: FnMut(&str) -> anyhow::Result<bool>,
+    {
+        Ok(match self {
+            Expr::Rule(name) => is_member(name)?,
+            Expr::Not(inner) => !inner.eval(is_member)?,
+            Expr::And(exprs) => {
+                for e in exprs {
+                    if !e.eval(is_member)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            Expr::Or(exprs) => {
+                for e in exprs {
+                    if e.eval(is_member)? {
+                        return Ok(true);
+                    }
+                }
+                false
+            }
+        })
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl FromStr for Query {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s).with_context(|| format!("tokenizing query: {:?}", s))?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser
+            .parse_or()
+            .with_context(|| format!("parsing query: {:?}", s))?;
+        if let Some(token) = parser.peek() {
+            anyhow::bail!("unexpected token {:?} in query: {:?}", token, s);
+        }
+        Ok(Self {
+            source: s.to_string(),
+            expr,
+        })
+    }
+}
+
+impl Serialize for Query {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Query {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|e| D::Error::custom(format!("{:#}", e)))
+    }
+}
+
+// Tokenizer //////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    And,
+    Or,
+    Not,
+    Any,
+    All,
+    Of,
+    Comma,
+    LParen,
+    RParen,
+    Name(String),
+}
+
+fn tokenize(s: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | ',' => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    _ => Token::Comma,
+                });
+            }
+            '"' => {
+                chars.next();
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => name.push(c),
+                        None => anyhow::bail!("unterminated quoted rule name: {:?}", name),
+                    }
+                }
+                tokens.push(Token::Name(name));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | ',' | '"') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(match word.to_ascii_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "any" => Token::Any,
+                    "all" => Token::All,
+                    "of" => Token::Of,
+                    _ => Token::Name(word),
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Parser /////////////////////////////////////////////////////////////////////
+
+/// A recursive descent parser over query tokens.
+///
+/// Grammar (lowest to highest precedence):
+///   or      := and ("OR" and)*
+///   and     := unary ("AND" unary)*
+///   unary   := "NOT" unary | primary
+///   primary := "(" or ")"
+///            | ("ANY" | "ALL") "(" or ("," or)* ")"
+///            | ("ANY" | "ALL") "OF" unary ("," unary)*
+///            | name
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &Token) -> anyhow::Result<()> {
+        match self.next() {
+            Some(t) if &t == token => Ok(()),
+            other => anyhow::bail!("expected {:?}, found {:?}", token, other),
+        }
+    }
+
+    fn parse_or(&mut self) -> anyhow::Result<Expr> {
+        let mut exprs = vec![self.parse_and()?];
+        while self.eat(&Token::Or) {
+            exprs.push(self.parse_and()?);
+        }
+        Ok(flatten(exprs, Expr::Or))
+    }
+
+    fn parse_and(&mut self) -> anyhow::Result<Expr> {
+        let mut exprs = vec![self.parse_unary()?];
+        while self.eat(&Token::And) {
+            exprs.push(self.parse_unary()?);
+        }
+        Ok(flatten(exprs, Expr::And))
+    }
+
+    fn parse_unary(&mut self) -> anyhow::Result<Expr> {
+        if self.eat(&Token::Not) {
+            Ok(Expr::Not(Box::new(self.parse_unary()?)))
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_primary(&mut self) -> anyhow::Result<Expr> {
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                self.expect(&Token::RParen)?;
+                Ok(expr)
+            }
+            Some(kind @ (Token::Any | Token::All)) => {
+                let exprs = if self.eat(&Token::LParen) {
+                    let mut exprs = vec![self.parse_or()?];
+                    while self.eat(&Token::Comma) {
+                        exprs.push(self.parse_or()?);
+                    }
+                    self.expect(&Token::RParen)?;
+                    exprs
+                } else {
+                    self.expect(&Token::Of)?;
+                    let mut exprs = vec![self.parse_unary()?];
+                    while self.eat(&Token::Comma) {
+                        exprs.push(self.parse_unary()?);
+                    }
+                    exprs
+                };
+                Ok(match kind {
+                    Token::Any => flatten(exprs, Expr::Or),
+                    _ => flatten(exprs, Expr::And),
+                })
+            }
+            Some(Token::Name(name)) => Ok(Expr::Rule(name)),
+            other => anyhow::bail!("expected a rule name or expression, found {:?}", other),
+        }
+    }
+}
+
+/// Avoids wrapping a single expression in an `And`/`Or`.
+fn flatten(mut exprs: Vec<Expr>, wrap: fn(Vec<Expr>) -> Expr) -> Expr {
+    if exprs.len() == 1 {
+        exprs.remove(0)
+    } else {
+        wrap(exprs)
+    }
+}
+
+// Resolution /////////////////////////////////////////////////////////////////
+
+/// Validates every query rule and fills in its watch_dirs.
+///
+/// - a rule cannot have both a `query` and a `regex`.
+/// - every rule referenced in a query must exist, and must not (transitively) reference itself.
+/// - if a query rule has no watch_dirs, it inherits the union of the watch_dirs of every rule
+///   it references.
+pub(crate) fn resolve_query_rules(rules: &mut [Rule]) -> anyhow::Result<()> {
+    let indices: HashMap<String, usize> = rules
+        .iter()
+        .enumerate()
+        .map(|(idx, rule)| (rule.name.clone(), idx))
+        .collect();
+
+    for rule in rules.iter() {
+        if let Some(query) = &rule.query {
+            anyhow::ensure!(
+                rule.regex.is_empty(),
+                "rule {:?}: `query` and `regex` are mutually exclusive",
+                rule.name
+            );
+            anyhow::ensure!(
+                rule.matchers.is_empty(),
+                "rule {:?}: `query` and `matchers` are mutually exclusive",
+                rule.name
+            );
+            for name in query.rule_names() {
+                anyhow::ensure!(
+                    indices.contains_key(name),
+                    "rule {:?}: query references unknown rule {:?}",
+                    rule.name,
+                    name
+                );
+            }
+        }
+    }
+
+    let mut resolved: HashMap<usize, Vec<PathBuf>> = HashMap::new();
+    for idx in 0..rules.len() {
+        let watch_dirs = resolve_watch_dirs(rules, &indices, idx, &mut vec![], &mut resolved)?;
+        rules[idx].watch_dirs = watch_dirs;
+    }
+
+    Ok(())
+}
+
+/// Depth-first resolution of a rule's watch_dirs, erroring on reference cycles.
+fn resolve_watch_dirs(
+    rules: &[Rule],
+    indices: &HashMap<String, usize>,
+    idx: usize,
+    visiting: &mut Vec<usize>,
+    resolved: &mut HashMap<usize, Vec<PathBuf>>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    if let Some(watch_dirs) = resolved.get(&idx) {
+        return Ok(watch_dirs.clone());
+    }
+    let rule = &rules[idx];
+    if visiting.contains(&idx) {
+        anyhow::bail!("rule {:?}: query references itself", rule.name);
+    }
+
+    let watch_dirs = match &rule.query {
+        Some(query) if rule.watch_dirs.is_empty() => {
+            visiting.push(idx);
+            let mut watch_dirs: Vec<PathBuf> = vec![];
+            for name in query.rule_names() {
+                for dir in resolve_watch_dirs(rules, indices, indices[name], visiting, resolved)? {
+                    if !watch_dirs.contains(&dir) {
+                        watch_dirs.push(dir);
+                    }
+                }
+            }
+            visiting.pop();
+            watch_dirs
+        }
+        Some(query) => {
+            // still walk the references to detect cycles
+            visiting.push(idx);
+            for name in query.rule_names() {
+                resolve_watch_dirs(rules, indices, indices[name], visiting, resolved)?;
+            }
+            visiting.pop();
+            rule.watch_dirs.clone()
+        }
+        None => rule.watch_dirs.clone(),
+    };
+
+    resolved.insert(idx, watch_dirs.clone());
+    Ok(watch_dirs)
+}
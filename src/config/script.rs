@@ -0,0 +1,223 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+#[cfg(feature = "scripting")]
+use std::{
+    sync::{Arc, LazyLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::MatchInput;
+
+// Script /////////////////////////////////////////////////////////////////////
+
+/// A rule's `script` predicate, in Rhai, compiled once when the config is loaded.
+///
+/// It's run for paths that are otherwise members, with these variables in scope:
+/// - `path`, `name`, `stem`, `ext`, `dir` and `rel_path` (relative to the watch_dir)
+/// - `captures`, the groups captured by the rule's regex, by index and by name, or whatever
+///   the registered matcher that matched captured
+/// - `is_dir`, `size` (in bytes), `modified` (Unix seconds), `age` (seconds) and `now`
+///
+/// It returns whether the path is a member, or a string to link it under that name instead of
+/// its own. `timestamp(year, month, day)` returns the Unix seconds of a date, for comparing
+/// dates found in names.
+///
+/// Scripts are sandboxed: they can't touch files or processes, and are stopped after a
+/// bounded number of operations.
+#[derive(Clone)]
+pub struct Script {
+    source: String,
+    #[cfg(feature = "scripting")]
+    ast: Arc<rhai::AST>,
+}
+
+/// What a script decided about a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptVerdict {
+    Reject,
+    Accept,
+    /// Accepted, and linked under this name instead of its own.
+    LinkAs(String),
+}
+
+impl Script {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Script").field(&self.source).finish()
+    }
+}
+
+#[cfg(feature = "scripting")]
+impl FromStr for Script {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ast = ENGINE
+            .compile(s)
+            .map_err(|e| anyhow::anyhow!("compiling script: {}", e))?;
+        Ok(Self {
+            source: s.to_string(),
+            ast: Arc::new(ast),
+        })
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+impl FromStr for Script {
+    type Err = anyhow::Error;
+
+    fn from_str(_: &str) -> Result<Self, Self::Err> {
+        anyhow::bail!("`script` needs filetags to be built with the `scripting` feature")
+    }
+}
+
+#[cfg(feature = "scripting")]
+impl Script {
+    /// Runs the script for the path, given the captures of the rule's regex or matcher.
+    pub fn eval(
+        &self,
+        input: &MatchInput,
+        captures: &BTreeMap<String, String>,
+    ) -> anyhow::Result<ScriptVerdict> {
+        use rhai::{Dynamic, Map, Scope};
+
+        let path = input.path;
+        let text = |part: Option<&std::ffi::OsStr>| {
+            part.map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        let now = unix_seconds(SystemTime::now());
+        let metadata = input.metadata();
+        let modified = metadata
+            .and_then(|m| m.modified().ok())
+            .map(unix_seconds)
+            .unwrap_or_default();
+
+        let mut scope = Scope::new();
+        scope
+            .push_constant("path", path.to_string_lossy().into_owned())
+            .push_constant("name", text(path.file_name()))
+            .push_constant("stem", text(path.file_stem()))
+            .push_constant("ext", text(path.extension()))
+            .push_constant("dir", text(path.parent().map(|p| p.as_os_str())))
+            .push_constant(
+                "rel_path",
+                text(
+                    path.strip_prefix(input.watch_dir)
+                        .ok()
+                        .map(|p| p.as_os_str()),
+                ),
+            )
+            .push_constant(
+                "captures",
+                captures
+                    .iter()
+                    .map(|(k, v)| (k.into(), Dynamic::from(v.clone())))
+                    .collect::<Map>(),
+            )
+            .push_constant("is_dir", metadata.is_some_and(|m| m.is_dir()))
+            .push_constant("size", metadata.map(|m| m.len() as i64).unwrap_or_default())
+            .push_constant("modified", modified)
+            .push_constant("age", now - modified)
+            .push_constant("now", now);
+
+        let result = ENGINE
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast)
+            .map_err(|e| anyhow::anyhow!("running script: {}", e))?;
+        if let Ok(accepted) = result.as_bool() {
+            return Ok(match accepted {
+                true => ScriptVerdict::Accept,
+                false => ScriptVerdict::Reject,
+            });
+        }
+        match result.into_string() {
+            Ok(name) if !name.is_empty() && !name.contains('/') => Ok(ScriptVerdict::LinkAs(name)),
+            Ok(name) => anyhow::bail!("script returned an invalid link name: {:?}", name),
+            Err(type_name) => {
+                anyhow::bail!("script returned a {}, not a bool or string", type_name)
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+impl Script {
+    pub fn eval(
+        &self,
+        _: &MatchInput,
+        _: &BTreeMap<String, String>,
+    ) -> anyhow::Result<ScriptVerdict> {
+        unreachable!("scripts can't be compiled without the `scripting` feature")
+    }
+}
+
+impl Serialize for Script {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Script {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|e| D::Error::custom(format!("{:#}", e)))
+    }
+}
+
+// Engine /////////////////////////////////////////////////////////////////////
+
+/// The most operations a script may run for a single path.
+#[cfg(feature = "scripting")]
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// The engine every script is compiled and run with.
+#[cfg(feature = "scripting")]
+static ENGINE: LazyLock<rhai::Engine> = LazyLock::new(|| {
+    let mut engine = rhai::Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(32)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(64 * 1024)
+        .set_max_array_size(10_000)
+        .set_max_map_size(10_000)
+        .disable_symbol("eval")
+        .on_print(|s| tracing::info!("script: {}", s))
+        .on_debug(|s, _, pos| tracing::debug!("script at {}: {}", pos, s))
+        .register_fn("timestamp", timestamp);
+    engine
+});
+
+#[cfg(feature = "scripting")]
+fn unix_seconds(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+/// Returns the Unix seconds at the start of a (proleptic Gregorian) date, in UTC.
+#[cfg(feature = "scripting")]
+fn timestamp(year: i64, month: i64, day: i64) -> i64 {
+    // days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    (era * 146_097 + day_of_era - 719_468) * 86_400
+}
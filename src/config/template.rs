@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context};
+
+use super::{RawRuleSettings, Rule};
+
+// Templates //////////////////////////////////////////////////////////////////
+
+/// Fills in the fields each rule leaves unset from the templates it `extends`, later templates
+/// taking precedence over earlier ones. Templates may extend others in turn.
+///
+/// A field is unset when it's empty or null. Settings are filled in one by one, and whatever
+/// is still unset afterwards is taken from the default settings.
+pub fn resolve_templates(rules: &mut [Rule], templates: &[Rule]) -> anyhow::Result<()> {
+    let mut by_name = HashMap::new();
+    for template in templates {
+        if by_name.insert(template.name.as_str(), template).is_some() {
+            bail!("more than one template is named {:?}", template.name);
+        }
+    }
+
+    let mut resolved = HashMap::new();
+    for template in templates {
+        resolve_template(&template.name, &by_name, &mut resolved, &mut vec![])?;
+    }
+    for rule in rules {
+        for name in rule.extends.clone().iter().rev() {
+            let template = resolved.get(name).with_context(|| {
+                format!("rule {:?}: no template is named {:?}", rule.name, name)
+            })?;
+            inherit(rule, template);
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves a template's own templates, depth-first. `stack` holds the templates being
+/// resolved, to catch cycles.
+fn resolve_template(
+    name: &str,
+    by_name: &HashMap<&str, &Rule>,
+    resolved: &mut HashMap<String, Rule>,
+    stack: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    if resolved.contains_key(name) {
+        return Ok(());
+    }
+    stack.push(name.to_string());
+    if stack[..stack.len() - 1].iter().any(|n| n == name) {
+        bail!(
+            "templates extend each other in a cycle: {}",
+            stack.join(" -> ")
+        );
+    }
+
+    let mut template = (*by_name
+        .get(name)
+        .with_context(|| format!("no template is named {:?}", name))?)
+    .clone();
+    for parent in template.extends.clone().iter().rev() {
+        resolve_template(parent, by_name, resolved, stack)
+            .with_context(|| format!("template {:?}", name))?;
+        inherit(&mut template, &resolved[parent]);
+    }
+    stack.pop();
+    resolved.insert(name.to_string(), template);
+
+    Ok(())
+}
+
+/// A field that's taken from a template when a rule leaves it unset.
+trait Inherit {
+    fn inherit(&mut self, from: &Self);
+}
+
+impl<T: Clone> Inherit for Option<T> {
+    fn inherit(&mut self, from: &Self) {
+        if self.is_none() {
+            self.clone_from(from);
+        }
+    }
+}
+
+impl<T: Clone> Inherit for Vec<T> {
+    fn inherit(&mut self, from: &Self) {
+        if self.is_empty() {
+            self.clone_from(from);
+        }
+    }
+}
+
+/// Destructures `$value` without `..`, so that a field added to `$ty` must be listed here too,
+/// and passes each listed field to `$apply!` in turn. Fields under `ignore` are left out on
+/// purpose.
+macro_rules! for_each_field {
+    (
+        $apply:ident,
+        $value:expr => $ty:ident { $($field:ident),+ $(,)? }
+        $(ignore { $($ignored:ident),+ $(,)? })?
+    ) => {{
+        let $ty { $($field,)+ $($($ignored: _,)+)? } = $value;
+        $(
+            $apply!($field);
+        )+
+    }};
+}
+pub(crate) use for_each_field;
+
+/// Fills in the fields of a rule from a single, resolved template. Names and `extends` are
+/// never inherited, and settings are filled in one by one.
+fn inherit(rule: &mut Rule, template: &Rule) {
+    macro_rules! inherit_field {
+        ($field:ident) => {
+            $field.inherit(&template.$field)
+        };
+    }
+    for_each_field!(
+        inherit_field,
+        &mut *rule => Rule {
+            watch_dirs,
+            link_dirs,
+            playlists,
+            regex,
+            matchers,
+            query,
+            xattr_tag,
+            sidecar_tag,
+            write_tag,
+            cookie,
+            untag_replace,
+            min_size,
+            max_size,
+            newer_than,
+            older_than,
+            extensions,
+            mime,
+            script,
+            on_link,
+            on_unlink,
+            on_conflict,
+        }
+        ignore { name, extends, watch_patterns, raw_settings, settings }
+    );
+    match (&mut rule.raw_settings, &template.raw_settings) {
+        (Some(settings), Some(from)) => inherit_settings(settings, from),
+        (settings @ None, from) => settings.clone_from(from),
+        (Some(_), None) => {}
+    }
+}
+
+fn inherit_settings(settings: &mut RawRuleSettings, from: &RawRuleSettings) {
+    macro_rules! inherit_setting {
+        ($field:ident) => {
+            $field.inherit(&from.$field)
+        };
+    }
+    for_each_field!(
+        inherit_setting,
+        settings => RawRuleSettings {
+            create_missing_dirs,
+            exclude_pattern,
+            max_depth,
+            follow_symlinks,
+            clean_interval,
+            targets,
+            link_inside_tagged_dirs,
+            inherit_from_parents,
+            match_on,
+            xattr_name,
+            write_xattr_tags,
+            untag_on_link_delete,
+            tag_on_link_drop,
+            propagate_link_renames,
+            remember_tags,
+            recall_policy,
+            dedupe,
+        }
+    );
+}
@@ -1,14 +1,23 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use actions::{
     cleaning::{clean_all, clean_dir},
     filesystem::make_necessary_dirs,
-    symlinking::{handle_notify_event, symlink_create_all},
-    Action,
+    playlists::{clean_playlists, write_dirty_playlists},
+    remembering::save_dirty_index,
+    symlinking::{handle_notify_event, symlink_create_all, symlink_create_found_dir},
+    tagging::handle_link_dir_event,
+    Action, ActionReport,
 };
 use anyhow::Context;
+use events::{Event, EventBus};
+use state::State;
+use tokio::sync::{broadcast, oneshot};
 use tokio::task::JoinHandle;
 use workers::{
+    event_server::start_event_server,
+    glob_watcher::start_glob_watchers,
+    link_watcher::{start_link_watchers, LinkDirEvent},
     periodic_cleaner::start_periodic_cleaners,
     watcher::{start_watchers, NotifyEvent},
     WorkerType,
@@ -17,15 +26,22 @@ use workers::{
 use crate::{Config, Receiver, Sender};
 
 pub mod actions;
+pub mod events;
+pub mod state;
 pub mod workers;
 
 // Message ////////////////////////////////////////////////////////////////////
 
 /// Message to be sent throgh the crossbeam channel.
-#[derive(Clone, Debug)]
+///
+/// Not `Clone`, since a `Request` holds the one sender its report goes back through.
+#[derive(Debug)]
 pub enum Message {
     NotifyEvent(NotifyEvent),
+    LinkDirEvent(LinkDirEvent),
     Action(Action),
+    /// An action whose report is sent back once it's finished, see `Dispatcher::perform`.
+    Request(Action, oneshot::Sender<ActionReport>),
     Shutdown,
 }
 
@@ -37,8 +53,12 @@ pub enum Message {
 pub struct Dispatcher {
     pub rx_handle: JoinHandle<anyhow::Result<()>>,
     pub tx: Sender<Message>,
+    /// The config as it was loaded. The responder's own copy also has the watch_dirs matched
+    /// by globs since.
     pub config: Arc<Config>,
     pub worker_handles: Vec<JoinHandle<anyhow::Result<()>>>,
+    /// Where the responder publishes events, see `subscribe`.
+    pub events: EventBus,
 }
 
 impl Dispatcher {
@@ -48,26 +68,65 @@ impl Dispatcher {
         tx: Sender<Message>,
         config: Arc<Config>,
     ) -> anyhow::Result<Self> {
+        let events = EventBus::default();
         Ok(Self {
-            rx_handle: Self::start_rx(rx, Arc::clone(&config)).context("starting rx")?,
+            rx_handle: Self::start_rx(rx, Arc::clone(&config), events.clone())
+                .context("starting rx")?,
             tx,
             config,
             worker_handles: vec![],
+            events,
         })
     }
 
     /// Starts the responder queue.
     /// For each Message it receives through rx, it handles it through `handle_message`.
+    ///
+    /// Errors are published as `Event::Error`. The responder then stops, unless the message was
+    /// a request, whose error is sent back in its report instead.
     fn start_rx(
         mut rx: Receiver<Message>,
         config: Arc<Config>,
+        events: EventBus,
     ) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
         Ok(tokio::spawn(async move {
+            // the responder's own copy, to which the watch_dirs found by globs are added
+            let mut config = config;
+            let mut state = State::new(&config, events).context("creating responder state")?;
             loop {
                 let message = rx.recv().await.context("Responder waiting for Message")?;
-                let maybe_signal =
-                    Self::handle_message(&message, &config).context("handling message")?;
-                if let Some(signal) = maybe_signal {
+                let (message, completion) = match message {
+                    Message::Request(action, completion) => {
+                        state.report = Some(ActionReport::default());
+                        (Message::Action(action), Some(completion))
+                    }
+                    message => (message, None),
+                };
+                let started = Instant::now();
+                let result = Self::handle_message(&message, &mut config, &mut state)
+                    .context("handling message")
+                    .and_then(|maybe_signal| {
+                        write_dirty_playlists(&config, &mut state).context("writing playlists")?;
+                        save_dirty_index(&mut state)?;
+                        Ok(maybe_signal)
+                    });
+                let result = result.inspect_err(|e| {
+                    state.events.publish(Event::Error {
+                        message: format!("{:#}", e),
+                    })
+                });
+
+                if let Some(completion) = completion {
+                    let mut report = state.report.take().unwrap_or_default();
+                    report.duration = started.elapsed();
+                    if let Err(e) = result {
+                        report.errors.push(e.into());
+                    }
+                    // the requester may have stopped waiting, which is fine
+                    let _ = completion.send(report);
+                    continue;
+                }
+                if let Some(signal) = result? {
                     match signal {
                         Signal::ShutdownSignal => break Ok(()),
                     }
@@ -77,24 +136,43 @@ impl Dispatcher {
     }
 
     /// Responds to each Message variant received. Invoked from `start_rx`.
-    fn handle_message(message: &Message, config: &Arc<Config>) -> anyhow::Result<Option<Signal>> {
+    fn handle_message(
+        message: &Message,
+        config: &mut Arc<Config>,
+        state: &mut State,
+    ) -> anyhow::Result<Option<Signal>> {
         match message {
             Message::Shutdown => return Ok(Some(Signal::ShutdownSignal)),
+            Message::Request(..) => unreachable!("requests are handled as actions"),
             Message::NotifyEvent(event) => {
-                handle_notify_event(config, event).context("handling notify event")?
+                handle_notify_event(config, state, event).context("handling notify event")?
+            }
+            Message::LinkDirEvent(event) => {
+                handle_link_dir_event(config, state, event).context("handling link_dir event")?
             }
             Message::Action(action) => match action {
                 Action::CleanAll => {
-                    clean_all(config).context("cleaning all")?;
+                    clean_all(config, state).context("cleaning all")?;
                 }
                 Action::CleanDir(rule_idx, link_idx) => {
-                    clean_dir(config, *rule_idx, *link_idx).context("cleaning dir")?
+                    clean_dir(config, state, *rule_idx, *link_idx).context("cleaning dir")?
+                }
+                Action::CleanPlaylists(rule_idx) => {
+                    clean_playlists(config, state, *rule_idx).context("cleaning playlists")?
                 }
                 Action::MakeNecessaryDirs => {
                     make_necessary_dirs(config).context("making necessary dirs")?;
                 }
                 Action::SymlinkAll => {
-                    symlink_create_all(config).context("maybe symlinking all")?;
+                    state.events.publish(Event::ScanStarted);
+                    symlink_create_all(config, state).context("maybe symlinking all")?;
+                    state.events.publish(Event::ScanFinished {
+                        links: state.links.len(),
+                    });
+                }
+                Action::AddWatchDir(rule_idx, dir) => {
+                    symlink_create_found_dir(config, state, *rule_idx, dir)
+                        .context("adding watch_dir")?
                 }
             },
         }
@@ -102,7 +180,13 @@ impl Dispatcher {
         Ok(None)
     }
 
+    /// Subscribes to the events published by the responder from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
     /// A buildable method for invoking an `Action` using the dispatcher.
+    /// It isn't waited for, see `perform` for that.
     pub fn run(self, action: Action) -> anyhow::Result<Self> {
         self.tx
             .send(Message::Action(action))
@@ -110,6 +194,25 @@ impl Dispatcher {
         Ok(self)
     }
 
+    /// Invokes an `Action`, and returns a receiver for its report, sent once it's finished.
+    pub fn request(&self, action: Action) -> anyhow::Result<oneshot::Receiver<ActionReport>> {
+        let (completion, report) = oneshot::channel();
+        self.tx
+            .send(Message::Request(action, completion))
+            .context("sending request")?;
+        Ok(report)
+    }
+
+    /// Invokes an `Action` and waits for its report.
+    ///
+    /// An error only means the responder had stopped; the action's own errors are in the
+    /// report (see `ActionReport::into_result`).
+    pub async fn perform(&self, action: Action) -> anyhow::Result<ActionReport> {
+        self.request(action)?
+            .await
+            .context("responder stopped before reporting")
+    }
+
     /// A buildable method for launching a `WorkerType`.
     /// For every worker thread that it launches, its handles will be appended to
     /// `self.worker_handles`.
@@ -120,6 +223,15 @@ impl Dispatcher {
             WorkerType::Watchers => {
                 start_watchers(&self.tx, &self.config).context("starting watchers")?
             }
+            WorkerType::GlobWatchers => {
+                start_glob_watchers(&self.tx, &self.config).context("starting glob watchers")?
+            }
+            WorkerType::LinkWatchers => {
+                start_link_watchers(&self.tx, &self.config).context("starting link watchers")?
+            }
+            WorkerType::EventServer => {
+                start_event_server(&self.events, &self.config).context("starting event server")?
+            }
         };
         // append the new worker handles to `self.worker_handles`
         self.worker_handles.append(&mut new_handles);
@@ -1,11 +1,92 @@
+use std::{fmt, mem, path::PathBuf, time::Duration};
+
+use crate::{events::Event, Error, Result};
+
 pub mod cleaning;
+pub mod deduplicating;
 pub mod filesystem;
+pub mod playlists;
+pub mod remembering;
 pub mod symlinking;
+pub mod tagging;
 
 #[derive(Clone, Debug)]
 pub enum Action {
     CleanDir(usize, usize),
+    CleanPlaylists(usize),
     MakeNecessaryDirs,
     CleanAll,
     SymlinkAll,
+    /// Adds a directory that appeared matching one of the rule's watch_dir globs, and links its
+    /// members (see `WorkerType::GlobWatchers`).
+    AddWatchDir(usize, PathBuf),
+}
+
+// ActionReport ///////////////////////////////////////////////////////////////
+
+/// What a requested action did, sent back once it's finished (see `Dispatcher::perform`).
+#[derive(Debug, Default)]
+pub struct ActionReport {
+    /// The symlinks created or retargeted.
+    pub created: Vec<PathBuf>,
+    /// The symlinks deleted.
+    pub removed: Vec<PathBuf>,
+    /// The sources that matched a rule but weren't linked or renamed, because of `dedupe` or a
+    /// conflict.
+    pub skipped: Vec<PathBuf>,
+    /// Why the action stopped early, if it did. What it did until then is still reported.
+    pub errors: Vec<Error>,
+    pub duration: Duration,
+}
+
+impl ActionReport {
+    /// Records what an event published during the action says was done.
+    pub fn record(&mut self, event: &Event) {
+        match event {
+            Event::LinkCreated { link_path, .. } | Event::LinkRetargeted { link_path, .. } => {
+                self.created.push(link_path.clone())
+            }
+            Event::LinkRemoved { link_path, .. } => self.removed.push(link_path.clone()),
+            Event::Conflict {
+                src_path,
+                link_path: None,
+                ..
+            } => self.skipped.push(src_path.clone()),
+            Event::Conflict { .. }
+            | Event::ScanStarted
+            | Event::ScanFinished { .. }
+            | Event::Error { .. } => (),
+        }
+    }
+
+    /// Appends another report, e.g. of the next action in a sequence.
+    pub fn merge(&mut self, other: ActionReport) {
+        self.created.extend(other.created);
+        self.removed.extend(other.removed);
+        self.skipped.extend(other.skipped);
+        self.errors.extend(other.errors);
+        self.duration += other.duration;
+    }
+
+    /// Returns the report, or its first error if the action stopped early.
+    pub fn into_result(mut self) -> Result<Self> {
+        match self.errors.is_empty() {
+            true => Ok(self),
+            false => Err(mem::take(&mut self.errors).remove(0)),
+        }
+    }
+}
+
+impl fmt::Display for ActionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} created, {} removed, {} skipped, {} errors in {:?}",
+            self.created.len(),
+            self.removed.len(),
+            self.skipped.len(),
+            self.errors.len(),
+            self.duration
+        )
+    }
 }
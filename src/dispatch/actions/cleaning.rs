@@ -1,6 +1,6 @@
 use std::{
-    fs::{self},
-    path::Path,
+    fs::{self, Metadata},
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
@@ -8,8 +8,17 @@ use anyhow::Context;
 use walkdir::WalkDir;
 
 use crate::{
-    delete_symlink, link_dir_indices, path_is_under_any_dirs, symlink_target,
-    utils::path_matches_any_regex, Config, Rule,
+    actions::{
+        deduplicating::dedupe_drops,
+        playlists::{clean_playlists, clean_playlists_under},
+        remembering::forget_source,
+        tagging::remove_source_tag,
+    },
+    delete_symlink,
+    events::Event,
+    link_dir_indices, path_is_under_any_dirs, query_depends_on, rule_accepts_target, rule_verdict,
+    state::State,
+    symlink_target, Config, ScriptVerdict,
 };
 
 // /// Shorthand for sending a query to the Receiver to `symlink_create_all`.
@@ -18,18 +27,27 @@ use crate::{
 //         .context("sending message")
 // }
 
-/// Runs `symlink_clean_dir` for every link_dir in config.
+/// Runs `symlink_clean_dir` for every link_dir in config, and `clean_playlists` for every rule.
 /// Ran from Receiver.
-pub fn clean_all(config: &Arc<Config>) -> anyhow::Result<()> {
+pub fn clean_all(config: &Arc<Config>, state: &mut State) -> anyhow::Result<()> {
     for (rule_idx, link_idx) in link_dir_indices(config) {
-        clean_dir(config, rule_idx, link_idx)?;
+        clean_dir(config, state, rule_idx, link_idx)?;
+    }
+    for rule_idx in 0..config.rules.len() {
+        clean_playlists(config, state, rule_idx)?;
     }
 
     Ok(())
 }
 
 /// Recursively cleans symlinks at the specified link_dir.
-pub fn clean_dir(config: &Arc<Config>, rule_idx: usize, link_idx: usize) -> anyhow::Result<()> {
+/// Publishes an event (and fires the rule's `on_unlink` hook) for every symlink deleted.
+pub fn clean_dir(
+    config: &Config,
+    state: &mut State,
+    rule_idx: usize,
+    link_idx: usize,
+) -> anyhow::Result<()> {
     let rule = &config.rules[rule_idx];
     let link_dir = &rule.link_dirs[link_idx];
 
@@ -41,35 +59,143 @@ pub fn clean_dir(config: &Arc<Config>, rule_idx: usize, link_idx: usize) -> anyh
         let metadata = fs::symlink_metadata(path)
             .with_context(|| format!("performing metadata call on path: {:?}", path))?;
 
-        // if is symlink, check if valid. if not, delete
-        if metadata.file_type().is_symlink() && inappropriate_symlink(path, rule)? {
-            delete_symlink(path, &metadata)?;
+        // symlinks dropped in by the user are handled by `handle_link_dir_event` instead
+        if rule.settings.tag_on_link_drop && state.is_foreign_link(path, &metadata) {
+            continue;
+        }
+
+        clean_link(config, state, rule_idx, path, &metadata)?;
+    }
+
+    Ok(())
+}
+
+/// Cleans the managed symlinks of the rule, and of the query rules that depend on it, that
+/// point to the paths or anything inside them, and removes them from the rules' playlists the
+/// same way. Used after paths were removed or retagged, so that the link_dirs aren't walked.
+pub fn clean_links_under(
+    config: &Config,
+    state: &mut State,
+    rule_idx: usize,
+    paths: &[PathBuf],
+) -> anyhow::Result<()> {
+    let rule_indices = (0..config.rules.len())
+        .filter(|idx| *idx == rule_idx || query_depends_on(config, *idx, rule_idx))
+        .collect::<Vec<_>>();
+
+    for path in paths {
+        for (symlink_path, link) in state.links_under(path) {
+            if !rule_indices.contains(&link.rule_idx) {
+                continue;
+            }
+            match fs::symlink_metadata(&symlink_path) {
+                Ok(metadata) => clean_link(config, state, link.rule_idx, &symlink_path, &metadata)?,
+                // deleted by something else in the meantime
+                Err(_) => {
+                    state.remove_link(&symlink_path);
+                }
+            }
+        }
+        for clean_idx in &rule_indices {
+            clean_playlists_under(config, state, *clean_idx, path)?;
         }
     }
 
     Ok(())
 }
 
+/// Deletes the symlink at the path if it's inappropriate for the rule, see
+/// `inappropriate_symlink`. Publishes an event (and fires the rule's `on_unlink` hook) if so.
+fn clean_link(
+    config: &Config,
+    state: &mut State,
+    rule_idx: usize,
+    path: &Path,
+    metadata: &Metadata,
+) -> anyhow::Result<()> {
+    if !metadata.file_type().is_symlink() || !inappropriate_symlink(path, config, state, rule_idx)?
+    {
+        return Ok(());
+    }
+
+    let rule = &config.rules[rule_idx];
+    let target_path = symlink_target(path)?;
+    // the target as written, which a broken symlink still has
+    let raw_target_path = fs::read_link(path).ok();
+    // forget it before deleting, so its removal event isn't mistaken for the user's
+    let link = state.remove_link(path);
+    delete_symlink(path, metadata)?;
+    if let Some(raw_target_path) = raw_target_path {
+        state.publish(
+            config,
+            rule_idx,
+            Event::link_removed(&rule.name, &raw_target_path, path),
+        );
+    }
+    if let Some(target_path) = target_path {
+        remove_source_tag(config, state, rule_idx, &target_path);
+        forget_source(config, state, rule_idx, &target_path);
+    } else if let Some(link) = link {
+        // the source is gone
+        remove_source_tag(config, state, rule_idx, &link.src_path);
+    }
+
+    Ok(())
+}
+
 /// Identifies whether the symlink at the path is inappropriate.
 ///
 /// It checks the following:
-/// - does the path match any of the regexes?
 /// - is the symlink broken?
 /// - is the symlink_target under any of the watch_dirs?
+/// - is the symlink_target a member of the rule (see `rule_matches`), or was it linked
+///   untagged because its content was remembered as tagged (see `RecallPolicy::Link`)?
+/// - is the symlink named as the rule's script says, if it names it (see `ScriptVerdict`)?
+/// - is the symlink_target of a kind the rule links (see `rule_accepts_target`)?
+/// - is the symlink_target a duplicate the rule doesn't link (see `dedupe_drops`)?
+///
+/// Membership is checked on the target rather than on the symlink's own name, so that links
+/// whose names differ from their source (conflicts, inherited tags, queries) are kept.
 ///
 /// Note that this function does not check whether the file at the path is a symlink or not.
 /// So do that validation beforehand.
-fn inappropriate_symlink(symlink_path: &Path, rule: &Rule) -> anyhow::Result<bool> {
-    // pattern doesnt match any regex
-    if !path_matches_any_regex(symlink_path, &rule.regex).context("matching regexes")? {
-        return Ok(true);
-    }
+fn inappropriate_symlink(
+    symlink_path: &Path,
+    config: &Config,
+    state: &mut State,
+    rule_idx: usize,
+) -> anyhow::Result<bool> {
+    let rule = &config.rules[rule_idx];
 
     if let Some(target_path) = symlink_target(symlink_path).context("getting symlink target")? {
         // is symlink_target is not under any watch_dirs?
         if !path_is_under_any_dirs(&target_path, &rule.watch_dirs)? {
             return Ok(true);
         }
+        // is symlink_target no longer a member of the rule?
+        let verdict = rule_verdict(config, rule_idx, &target_path).context("matching rule")?;
+        if verdict == ScriptVerdict::Reject
+            && !state
+                .tag_index
+                .as_ref()
+                .is_some_and(|index| index.recalls_link(&target_path, &rule.name))
+        {
+            return Ok(true);
+        }
+        // is the symlink no longer named as the rule's script says?
+        if let ScriptVerdict::LinkAs(name) = &verdict {
+            if !is_link_name_for(symlink_path, name) {
+                return Ok(true);
+            }
+        }
+        // is symlink_target not something the rule links, e.g. inside a tagged dir?
+        if !rule_accepts_target(config, rule_idx, &target_path)? {
+            return Ok(true);
+        }
+        // is symlink_target a duplicate of a source the rule links instead?
+        if dedupe_drops(config, state, rule_idx, &target_path)? {
+            return Ok(true);
+        }
     } else {
         // symlink target unreachable, broken
         return Ok(true);
@@ -77,3 +203,21 @@ fn inappropriate_symlink(symlink_path: &Path, rule: &Rule) -> anyhow::Result<boo
 
     Ok(false)
 }
+
+/// Returns whether the symlink has the name a script chose for it, or that name made unique
+/// after a conflict (see `validate_existing_symlink`).
+fn is_link_name_for(symlink_path: &Path, name: &str) -> bool {
+    let name = Path::new(name).file_name().unwrap_or_default();
+    let Some(mut link_name) = symlink_path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    loop {
+        if link_name == name {
+            return true;
+        }
+        match link_name.strip_prefix("0_") {
+            Some(rest) => link_name = rest,
+            None => return false,
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use tracing::{info, warn};
+use walkdir::WalkDir;
+
+use crate::{
+    delete_symlink, events::Event, state::State, symlink_target, Config, ContentKey, Dedupe,
+    HashCache,
+};
+
+/// Links of a rule whose sources have identical content.
+#[derive(Debug, Clone)]
+pub struct DuplicateGroup {
+    pub key: ContentKey,
+    /// The symlinks and the sources they point to.
+    pub links: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Applies the rule's `dedupe` setting to a source that's about to be linked.
+/// Returns whether it should be linked.
+///
+/// Only sources the rule already links are compared against, through the content keys cached
+/// when they were linked.
+pub fn dedupe_before_linking(
+    config: &Config,
+    state: &mut State,
+    rule_idx: usize,
+    src_path: &Path,
+) -> anyhow::Result<bool> {
+    let rule = &config.rules[rule_idx];
+    let Some(dedupe) = rule.settings.dedupe else {
+        return Ok(true);
+    };
+    if !src_path.is_file() {
+        return Ok(true);
+    }
+
+    let key = state.hashes.get(src_path)?;
+    let mut duplicates = vec![];
+    for path in state.hashes.cached_paths(&key) {
+        if path != src_path
+            && !state.links_to(rule_idx, &path).is_empty()
+            && state.hashes.get(&path).is_ok_and(|k| k == key)
+        {
+            duplicates.push(path);
+        }
+    }
+    if duplicates.is_empty() {
+        return Ok(true);
+    }
+    let already_linked = !state.links_to(rule_idx, src_path).is_empty();
+
+    match dedupe {
+        Dedupe::Report => {
+            if !already_linked {
+                warn!(
+                    "duplicate: {:?} has the same content as {:?}, linked for rule {:?}",
+                    src_path, duplicates, rule.name
+                );
+            }
+            Ok(true)
+        }
+        Dedupe::KeepFirst => {
+            if !already_linked {
+                info!(
+                    "not linking {:?}, it has the same content as {:?}",
+                    src_path, duplicates
+                );
+                state.skip(src_path);
+            }
+            Ok(already_linked)
+        }
+        Dedupe::KeepShortestPath => {
+            let shortest = duplicates
+                .iter()
+                .map(PathBuf::as_path)
+                .chain([src_path])
+                .min_by_key(|path| (path.as_os_str().len(), *path))
+                .unwrap_or(src_path);
+            if shortest != src_path {
+                info!(
+                    "not linking {:?}, it has the same content as {:?}",
+                    src_path, shortest
+                );
+                unlink_source(config, state, rule_idx, src_path)?;
+                state.skip(src_path);
+                return Ok(false);
+            }
+            for duplicate in &duplicates {
+                info!(
+                    "unlinking {:?}, it has the same content as {:?}",
+                    duplicate, src_path
+                );
+                unlink_source(config, state, rule_idx, duplicate)?;
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// Returns whether the rule's `dedupe` setting leaves the source unlinked, i.e. it would keep
+/// one link per content and the rule already links a duplicate of the source instead. Used by
+/// the cleaner for links to duplicates left from before.
+pub fn dedupe_drops(
+    config: &Config,
+    state: &mut State,
+    rule_idx: usize,
+    src_path: &Path,
+) -> anyhow::Result<bool> {
+    let keeps_one = matches!(
+        config.rules[rule_idx].settings.dedupe,
+        Some(Dedupe::KeepFirst | Dedupe::KeepShortestPath)
+    );
+    if !keeps_one || !src_path.is_file() || !state.links_to(rule_idx, src_path).is_empty() {
+        return Ok(false);
+    }
+
+    let key = state.hashes.get(src_path)?;
+    for path in state.hashes.cached_paths(&key) {
+        if path != src_path
+            && !state.links_to(rule_idx, &path).is_empty()
+            && state.hashes.get(&path).is_ok_and(|k| k == key)
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Deletes the managed symlinks of the rule that point to the source, publishing an event (and
+/// firing its `on_unlink` hook) for each.
+fn unlink_source(
+    config: &Config,
+    state: &mut State,
+    rule_idx: usize,
+    src_path: &Path,
+) -> anyhow::Result<()> {
+    for symlink_path in state.links_to(rule_idx, src_path) {
+        // forget it before deleting, so its removal event isn't mistaken for the user's
+        state.remove_link(&symlink_path);
+        if let Ok(metadata) = fs::symlink_metadata(&symlink_path) {
+            delete_symlink(&symlink_path, &metadata)?;
+            state.publish(
+                config,
+                rule_idx,
+                Event::link_removed(&config.rules[rule_idx].name, src_path, &symlink_path),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds the links in the rule's link_dirs whose sources have identical content.
+///
+/// Sources are first grouped by size, so only files that share their size with another
+/// source are hashed.
+pub fn find_duplicates(
+    config: &Config,
+    rule_idx: usize,
+    hashes: &mut HashCache,
+) -> anyhow::Result<Vec<DuplicateGroup>> {
+    let mut by_size: HashMap<u64, Vec<(PathBuf, PathBuf)>> = HashMap::new();
+    for link_dir in &config.rules[rule_idx].link_dirs {
+        for entry in WalkDir::new(link_dir) {
+            let entry = entry?;
+            if !entry.path_is_symlink() {
+                continue;
+            }
+            let Some(src_path) = symlink_target(entry.path())? else {
+                continue;
+            };
+            if let Ok(metadata) = fs::metadata(&src_path) {
+                if metadata.is_file() {
+                    by_size
+                        .entry(metadata.len())
+                        .or_default()
+                        .push((entry.path().to_path_buf(), src_path));
+                }
+            }
+        }
+    }
+
+    let mut by_key: BTreeMap<ContentKey, Vec<(PathBuf, PathBuf)>> = BTreeMap::new();
+    for links in by_size.into_values() {
+        if !has_distinct_sources(&links) {
+            continue;
+        }
+        for (symlink_path, src_path) in links {
+            let key = hashes.get(&src_path)?;
+            by_key
+                .entry(key)
+                .or_default()
+                .push((symlink_path, src_path));
+        }
+    }
+
+    Ok(by_key
+        .into_iter()
+        .filter(|(_, links)| has_distinct_sources(links))
+        .map(|(key, mut links)| {
+            links.sort();
+            DuplicateGroup { key, links }
+        })
+        .collect())
+}
+
+/// Returns whether the links point to more than one source.
+fn has_distinct_sources(links: &[(PathBuf, PathBuf)]) -> bool {
+    links
+        .first()
+        .is_some_and(|(_, first)| links.iter().any(|(_, src_path)| src_path != first))
+}
@@ -0,0 +1,195 @@
+use std::{
+    cmp::Reverse,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use tracing::debug;
+
+use crate::{
+    relative_path, rule_accepts_target, rule_matches, state::State, write_file_atomically, Config,
+    Playlist, PlaylistFormat, PlaylistSort, Rule,
+};
+
+/// Adds a member to the playlists of the rule, if it has any.
+pub fn add_to_playlists(config: &Config, state: &mut State, rule_idx: usize, src_path: &Path) {
+    if config.rules[rule_idx].playlists.is_empty() {
+        return;
+    }
+    let added = state
+        .playlist_members
+        .entry(rule_idx)
+        .or_default()
+        .insert(src_path.to_path_buf());
+    if added {
+        state.dirty_playlists.insert(rule_idx);
+    }
+}
+
+/// Removes paths that are no longer members from the playlists of the rule, the same way
+/// `clean_dir` removes their links.
+pub fn clean_playlists(config: &Config, state: &mut State, rule_idx: usize) -> anyhow::Result<()> {
+    clean_playlist_members(config, state, rule_idx, |_| true)
+}
+
+/// Like `clean_playlists`, but only checks the members at or inside the path.
+pub fn clean_playlists_under(
+    config: &Config,
+    state: &mut State,
+    rule_idx: usize,
+    path: &Path,
+) -> anyhow::Result<()> {
+    clean_playlist_members(config, state, rule_idx, |member| member.starts_with(path))
+}
+
+fn clean_playlist_members(
+    config: &Config,
+    state: &mut State,
+    rule_idx: usize,
+    check: impl Fn(&Path) -> bool,
+) -> anyhow::Result<()> {
+    let Some(members) = state.playlist_members.get(&rule_idx) else {
+        return Ok(());
+    };
+
+    let mut stale = vec![];
+    for path in members.iter().filter(|path| check(path)) {
+        if !path.exists()
+            || !rule_matches(config, rule_idx, path)?
+            || !rule_accepts_target(config, rule_idx, path)?
+        {
+            stale.push(path.clone());
+        }
+    }
+    if stale.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(members) = state.playlist_members.get_mut(&rule_idx) {
+        for path in &stale {
+            debug!("Removing from playlists: {:?}", path);
+            members.remove(path);
+        }
+    }
+    state.dirty_playlists.insert(rule_idx);
+
+    Ok(())
+}
+
+/// Writes the playlists of every rule whose members changed.
+/// Called from the Receiver after each message, so that a batch of changes is written once.
+pub fn write_dirty_playlists(config: &Config, state: &mut State) -> anyhow::Result<()> {
+    for rule_idx in std::mem::take(&mut state.dirty_playlists) {
+        let rule = &config.rules[rule_idx];
+        let members = state
+            .playlist_members
+            .get(&rule_idx)
+            .map(|members| members.iter().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        for playlist in &rule.playlists {
+            write_playlist(rule, playlist, members.clone())
+                .with_context(|| format!("writing playlist: {:?}", playlist.path))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sorts the members and writes them to the playlist, atomically.
+fn write_playlist(
+    rule: &Rule,
+    playlist: &Playlist,
+    mut members: Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    match playlist.sort {
+        PlaylistSort::Path => members.sort(),
+        PlaylistSort::Name => members.sort_by(|a, b| (a.file_name(), a).cmp(&(b.file_name(), b))),
+        PlaylistSort::Modified => members.sort_by_cached_key(|path| {
+            let modified = fs::metadata(path).and_then(|m| m.modified());
+            (
+                Reverse(modified.unwrap_or(SystemTime::UNIX_EPOCH)),
+                path.clone(),
+            )
+        }),
+    }
+
+    let playlist_dir = playlist.path.parent().unwrap_or(Path::new("/"));
+    let entries = members.iter().map(|path| match playlist.relative_paths {
+        true => relative_path(playlist_dir, path),
+        false => path.clone(),
+    });
+
+    debug!(
+        "Writing playlist with {} entries: {:?}",
+        members.len(),
+        playlist.path
+    );
+    let contents = match playlist.format() {
+        PlaylistFormat::M3u8 => render_m3u8(entries),
+        PlaylistFormat::Xspf => render_xspf(&rule.name, entries, playlist.relative_paths),
+    };
+    write_file_atomically(&playlist.path, contents)
+}
+
+fn render_m3u8(entries: impl Iterator<Item = PathBuf>) -> String {
+    let mut contents = String::from("#EXTM3U\n");
+    for entry in entries {
+        contents.push_str(&entry.to_string_lossy());
+        contents.push('\n');
+    }
+    contents
+}
+
+fn render_xspf(title: &str, entries: impl Iterator<Item = PathBuf>, relative: bool) -> String {
+    let mut contents = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+    );
+    let _ = writeln!(contents, "  <title>{}</title>", escape_xml(title));
+    contents.push_str("  <trackList>\n");
+    for entry in entries {
+        let location = match relative {
+            true => encode_uri_path(&entry),
+            false => format!("file://{}", encode_uri_path(&entry)),
+        };
+        let title = entry
+            .file_stem()
+            .map(|s| s.to_string_lossy())
+            .unwrap_or_default();
+        let _ = writeln!(
+            contents,
+            "    <track><location>{}</location><title>{}</title></track>",
+            escape_xml(&location),
+            escape_xml(&title)
+        );
+    }
+    contents.push_str("  </trackList>\n</playlist>\n");
+    contents
+}
+
+/// Percent-encodes a path for use in a URI, keeping its slashes.
+fn encode_uri_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for byte in path.as_os_str().as_encoded_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(*byte as char)
+            }
+            _ => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+    }
+    encoded
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,138 @@
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use tracing::{debug, info, warn};
+
+use crate::{
+    actions::{
+        symlinking::{symlink_create, symlink_into_link_dirs},
+        tagging::tag_source,
+    },
+    rule_matches, rule_script_verdict,
+    state::State,
+    watch_idx_for_path, Config, ContentKey, RecallPolicy,
+};
+
+/// Remembers the content of a source that was just linked, if the rule has `remember_tags` set.
+///
+/// Files are only hashed when their path isn't already remembered for the rule.
+/// Failures are logged rather than returned.
+pub fn remember_source(config: &Config, state: &mut State, rule_idx: usize, src_path: &Path) {
+    let rule = &config.rules[rule_idx];
+    let Some(index) = state.tag_index.as_mut() else {
+        return;
+    };
+    if !rule.settings.remember_tags || !src_path.is_file() || index.recalls(src_path, &rule.name) {
+        return;
+    }
+
+    match ContentKey::of_file(src_path) {
+        Ok(key) => {
+            debug!(
+                "Remembering {} for rule {:?}: {:?}",
+                key, rule.name, src_path
+            );
+            state.dirty_index |= index.remember(key, src_path, &rule.name);
+        }
+        Err(e) => warn!("failed to remember tag of {:?}: {:#}", src_path, e),
+    }
+}
+
+/// Forgets that the content of a source is tagged for the rule, after it was untagged.
+/// Sources that no longer exist are kept in the index, so their tags can be recalled.
+///
+/// Failures are logged rather than returned.
+pub fn forget_source(config: &Config, state: &mut State, rule_idx: usize, src_path: &Path) {
+    let rule = &config.rules[rule_idx];
+    let Some(index) = state.tag_index.as_mut() else {
+        return;
+    };
+    if !rule.settings.remember_tags || !src_path.is_file() {
+        return;
+    }
+    // still a member, e.g. the link was only deleted for being inside a tagged dir
+    if rule_matches(config, rule_idx, src_path).unwrap_or(false) {
+        return;
+    }
+
+    match ContentKey::of_file(src_path) {
+        Ok(key) => {
+            debug!(
+                "Forgetting {} for rule {:?}: {:?}",
+                key, rule.name, src_path
+            );
+            state.dirty_index |= index.forget_tag(key, &rule.name);
+        }
+        Err(e) => warn!("failed to forget tag of {:?}: {:#}", src_path, e),
+    }
+}
+
+/// Saves the tag index if it changed.
+/// Called from the Receiver after each message, so that a batch of changes is saved once.
+pub fn save_dirty_index(state: &mut State) -> anyhow::Result<()> {
+    if !std::mem::take(&mut state.dirty_index) {
+        return Ok(());
+    }
+    match &state.tag_index {
+        Some(index) => index.save().context("saving tag index"),
+        None => Ok(()),
+    }
+}
+
+/// Recalls the tag of an untagged file that appeared in a watch_dir, if its content was
+/// remembered as tagged for the rule. What happens then depends on the `recall_policy` setting.
+pub fn recall_tags(
+    config: &Config,
+    state: &mut State,
+    rule_idx: usize,
+    src_path: &Path,
+) -> anyhow::Result<()> {
+    let rule = &config.rules[rule_idx];
+    if state.tag_index.is_none() || !rule.settings.remember_tags || rule.query.is_some() {
+        return Ok(());
+    }
+    // empty files are usually still being written, and all look alike
+    if !fs::metadata(src_path).is_ok_and(|m| m.is_file() && m.len() > 0)
+        || rule_matches(config, rule_idx, src_path)?
+    {
+        return Ok(());
+    }
+    let Some(index) = state.tag_index.as_mut() else {
+        return Ok(());
+    };
+    if index.recalls(src_path, &rule.name) {
+        return Ok(());
+    }
+
+    let key = ContentKey::of_file(src_path)?;
+    let Some(entry) = index.get(&key).filter(|e| e.tags.contains(&rule.name)) else {
+        return Ok(());
+    };
+
+    match rule.settings.recall_policy {
+        RecallPolicy::Offer => {
+            info!(
+                "{:?} has the same content as {:?}, which was tagged for rule {:?}. tag it with `filetags index apply {:?}`",
+                src_path, entry.paths, rule.name, src_path
+            );
+        }
+        RecallPolicy::Rename => {
+            info!("recalled tag of rule {:?} for {:?}", rule.name, src_path);
+            let tagged_path = tag_source(config, Some(state), rule_idx, src_path)?;
+            if let Some(watch_idx) = watch_idx_for_path(config, rule_idx, &tagged_path) {
+                symlink_create(config, state, &tagged_path, rule_idx, watch_idx)?;
+            }
+        }
+        RecallPolicy::Link => {
+            info!("recalled tag of rule {:?} for {:?}", rule.name, src_path);
+            state.dirty_index |= index.remember_link(key, src_path, &rule.name);
+            if let Some(watch_idx) = watch_idx_for_path(config, rule_idx, src_path) {
+                // the script may still name the links
+                let verdict = rule_script_verdict(config, rule_idx, src_path)?;
+                symlink_into_link_dirs(config, state, src_path, rule_idx, watch_idx, &verdict)?;
+            }
+        }
+    }
+
+    Ok(())
+}
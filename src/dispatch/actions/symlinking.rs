@@ -1,49 +1,263 @@
 use std::{
     fs::{self, Metadata},
     os::unix::fs::symlink,
-    path::Path,
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
 use anyhow::Context;
 use notify::{
-    event::{ModifyKind, RenameMode},
+    event::{AccessKind, AccessMode, MetadataKind, ModifyKind, RenameMode},
     EventKind,
 };
 use tracing::debug;
 use walkdir::WalkDir;
 
 use crate::{
-    delete_symlink, get_basename, match_event_kinds, symlink_target,
-    utils::{calc_link_from_src_orig, path_matches_any_regex},
-    watch_dir_indices_with_refs,
+    actions::{
+        cleaning::{clean_dir, clean_links_under},
+        deduplicating::dedupe_before_linking,
+        playlists::add_to_playlists,
+        remembering::{recall_tags, remember_source},
+        tagging::write_source_tag,
+    },
+    delete_symlink,
+    events::Event,
+    get_basename, is_sidecar, link_dir_indices, match_event_kinds, match_metadata_event_kinds,
+    match_removal_event_kinds, query_depends_on, rule_accepts_target, rule_verdict,
+    sidecar_described_paths,
+    state::State,
+    symlink_target,
+    utils::calc_link_from_src_orig,
+    watch_dir_indices_with_refs, watch_idx_for_path,
     workers::watcher::NotifyEvent,
-    Config,
+    Config, Error, ScriptVerdict,
 };
 
 /// Handle a notify event.
 /// Called from the Receiver.
 ///
-/// Runs `maybe_symlink_path` if the notify event matches `match_event_kinds!()`.
-pub fn handle_notify_event(config: &Config, message: &NotifyEvent) -> anyhow::Result<()> {
+/// Runs `symlink_create_recursive` if the notify event matches `match_event_kinds!()`.
+/// Cleans the links into the paths if it matches `match_removal_event_kinds!()`.
+/// Does both if it matches `match_metadata_event_kinds!()`, as the path's xattr tags may have
+/// been added or removed.
+/// Runs `recall_tags` for new files, and for files that were written to, since a copy may
+/// have been created empty.
+///
+/// All are also done for every query rule that depends on the rule the event came from.
+pub fn handle_notify_event(
+    config: &Config,
+    state: &mut State,
+    message: &NotifyEvent,
+) -> anyhow::Result<()> {
+    if config.rules[message.rule_idx].sidecar_tag.is_some()
+        && message.event.paths.iter().any(|p| is_sidecar(p))
+    {
+        return handle_sidecar_event(config, state, message)
+            .context("handling sidecar notify event");
+    }
+
     match message.event.kind {
         match_event_kinds!() => {
             debug!("Received new notify event!: {:?}", message.event.kind);
             for check_path in &message.event.paths {
-                symlink_create(config, check_path, message.rule_idx, message.watch_idx)
-                    .context("handling path for notify event")?;
+                symlink_create_recursive(
+                    config,
+                    state,
+                    check_path,
+                    message.rule_idx,
+                    message.watch_idx,
+                )
+                .context("handling path for notify event")?;
+            }
+            // files moved within the watch_dirs keep their tags, or were untagged on purpose
+            let moved_within = matches!(
+                message.event.kind,
+                EventKind::Modify(ModifyKind::Name(RenameMode::To))
+            ) && message
+                .event
+                .tracker()
+                .is_some_and(|tracker| state.has_recent_move(tracker));
+            if !moved_within {
+                recall_tags_recursive(config, state, &message.event.paths, message.rule_idx)
+                    .context("recalling tags for notify event")?;
+            }
+        }
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+            debug!("Received new write notify event!: {:?}", message.event.kind);
+            recall_tags_recursive(config, state, &message.event.paths, message.rule_idx)
+                .context("recalling tags for written file")?;
+        }
+        match_removal_event_kinds!() => {
+            debug!(
+                "Received new removal notify event!: {:?}",
+                message.event.kind
+            );
+            // remember where moved paths came from, in case they were dropped into a link_dir
+            if let (EventKind::Modify(ModifyKind::Name(RenameMode::From)), Some(tracker)) =
+                (message.event.kind, message.event.tracker())
+            {
+                for path in &message.event.paths {
+                    state.insert_recent_move(tracker, path);
+                }
+            }
+            clean_links_under(config, state, message.rule_idx, &message.event.paths)
+                .context("cleaning links after removal")?;
+        }
+        match_metadata_event_kinds!() => {
+            debug!(
+                "Received new metadata notify event!: {:?}",
+                message.event.kind
+            );
+            for check_path in &message.event.paths {
+                symlink_create_recursive(
+                    config,
+                    state,
+                    check_path,
+                    message.rule_idx,
+                    message.watch_idx,
+                )
+                .context("handling path for metadata notify event")?;
             }
+            clean_links_under(config, state, message.rule_idx, &message.event.paths)
+                .context("cleaning links after metadata change")?;
         }
         _ => (),
     }
     Ok(())
 }
 
-/// Runs `symlink_create` for every watch_dir in Config, recursively.
-pub fn symlink_create_all(config: &Arc<Config>) -> anyhow::Result<()> {
+/// Handle a notify event for a changed, created or removed sidecar file.
+///
+/// Runs `symlink_create_recursive` for every path the sidecar describes, then cleans the links
+/// into the sidecar's directory, since tags may have been both added and removed.
+fn handle_sidecar_event(
+    config: &Config,
+    state: &mut State,
+    message: &NotifyEvent,
+) -> anyhow::Result<()> {
+    debug!("Received new sidecar notify event!: {:?}", message.event);
+    for sidecar_path in message.event.paths.iter().filter(|p| is_sidecar(p)) {
+        for described_path in sidecar_described_paths(sidecar_path)? {
+            symlink_create_recursive(
+                config,
+                state,
+                &described_path,
+                message.rule_idx,
+                message.watch_idx,
+            )
+            .context("handling path described by sidecar")?;
+        }
+    }
+    // a sidecar may describe its whole directory
+    let dirs = message
+        .event
+        .paths
+        .iter()
+        .filter(|p| is_sidecar(p))
+        .filter_map(|p| p.parent().map(Path::to_path_buf))
+        .collect::<Vec<_>>();
+    clean_links_under(config, state, message.rule_idx, &dirs)
+        .context("cleaning links after sidecar change")
+}
+
+/// Runs `recall_tags` for the paths and, if they're directories, everything inside them.
+fn recall_tags_recursive(
+    config: &Config,
+    state: &mut State,
+    paths: &[PathBuf],
+    rule_idx: usize,
+) -> anyhow::Result<()> {
+    if !config.rules[rule_idx].settings.remember_tags {
+        return Ok(());
+    }
+    for path in paths {
+        for direntry in WalkDir::new(path) {
+            let Ok(direntry) = direntry else {
+                // the path may be gone by the time the event is handled
+                continue;
+            };
+            recall_tags(config, state, rule_idx, direntry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs `symlink_create` for the path and, if it's a directory, everything inside it.
+/// Also runs it for every query rule that depends on the rule at `rule_idx`.
+///
+/// A directory that was moved or renamed into place produces no events for its contents, and
+/// renaming a directory can change the membership of its descendants (`inherit_from_parents`).
+fn symlink_create_recursive(
+    config: &Config,
+    state: &mut State,
+    src_path: &Path,
+    rule_idx: usize,
+    watch_idx: usize,
+) -> anyhow::Result<()> {
+    // the path may be gone by the time the event is handled
+    if !src_path.exists() {
+        debug!("Path no longer exists, skipping: {:?}", src_path);
+        return Ok(());
+    }
+
+    for direntry in WalkDir::new(src_path).sort_by_file_name() {
+        let direntry = direntry?;
+        symlink_create(config, state, direntry.path(), rule_idx, watch_idx)?;
+        symlink_create_dependents(config, state, direntry.path(), rule_idx)
+            .context("handling path in dependent query rules")?;
+    }
+
+    Ok(())
+}
+
+/// Runs `symlink_create` for every watch_dir in Config, recursively, in path order.
+///
+/// The link_dirs of rules with `dedupe` set are cleaned afterwards, since links left over to
+/// duplicates can only be told apart once every copy was seen.
+pub fn symlink_create_all(config: &Arc<Config>, state: &mut State) -> anyhow::Result<()> {
     for (rule_idx, watch_idx, _, watch_dir) in watch_dir_indices_with_refs(config) {
-        for direntry in WalkDir::new(watch_dir) {
-            symlink_create(config, direntry.unwrap().path(), rule_idx, watch_idx)?;
+        for direntry in WalkDir::new(watch_dir).sort_by_file_name() {
+            symlink_create(config, state, direntry.unwrap().path(), rule_idx, watch_idx)?;
+        }
+    }
+    for (rule_idx, link_idx) in link_dir_indices(config) {
+        if config.rules[rule_idx].settings.dedupe.is_some() {
+            clean_dir(config, state, rule_idx, link_idx)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds a directory matched by one of the rule's watch_dir globs to its watch_dirs, then runs
+/// `symlink_create_recursive` for it.
+pub fn symlink_create_found_dir(
+    config: &mut Arc<Config>,
+    state: &mut State,
+    rule_idx: usize,
+    dir: &Path,
+) -> anyhow::Result<()> {
+    let watch_idx = Arc::make_mut(config).rules[rule_idx].add_watch_dir(dir);
+    debug!("Found watch_dir {:?} of rule {}", dir, rule_idx);
+    symlink_create_recursive(config, state, dir, rule_idx, watch_idx)
+}
+
+/// Runs `symlink_create` for every query rule that depends on the rule at `rule_idx`,
+/// as the membership of the path in that rule may have changed.
+fn symlink_create_dependents(
+    config: &Config,
+    state: &mut State,
+    src_path: &Path,
+    rule_idx: usize,
+) -> anyhow::Result<()> {
+    for query_idx in 0..config.rules.len() {
+        if !query_depends_on(config, query_idx, rule_idx) {
+            continue;
+        }
+        if let Some(watch_idx) = watch_idx_for_path(config, query_idx, src_path) {
+            symlink_create(config, state, src_path, query_idx, watch_idx)?;
         }
     }
 
@@ -52,39 +266,91 @@ pub fn symlink_create_all(config: &Arc<Config>) -> anyhow::Result<()> {
 
 /// Maybe create a symlink to the given path.
 ///
-/// First it checks if it matches any of the regexes. If it matches, then create a symlink
-/// if not already created.
+/// First it checks if it's a member of the rule. If it matches, then create a symlink
+/// if not already created, unless the rule's `dedupe` setting says otherwise.
 pub fn symlink_create(
     config: &Config,
+    state: &mut State,
+    src_path: &Path,
+    rule_idx: usize,
+    watch_idx: usize,
+) -> anyhow::Result<()> {
+    let verdict = rule_verdict(config, rule_idx, src_path)?;
+    if verdict != ScriptVerdict::Reject {
+        debug!("Rule matches! {:?}", src_path);
+
+        if rule_accepts_target(config, rule_idx, src_path)?
+            && dedupe_before_linking(config, state, rule_idx, src_path)?
+        {
+            symlink_into_link_dirs(config, state, src_path, rule_idx, watch_idx, &verdict)?;
+            add_to_playlists(config, state, rule_idx, src_path);
+            write_source_tag(config, state, rule_idx, src_path);
+            remember_source(config, state, rule_idx, src_path);
+        }
+    }
+
+    Ok(())
+}
+
+/// For every link_dir of the rule, check if the expected link_path has a symlink to the path,
+/// and if not, create one. Membership isn't checked, see `symlink_create` for that, but the
+/// script's verdict on the path is taken for the name of the links (see `rule_verdict`).
+///
+/// Publishes an event (and fires the rule's hook) for every symlink created or retargeted, and
+/// for every expected link_path that was taken.
+pub fn symlink_into_link_dirs(
+    config: &Config,
+    state: &mut State,
     src_path: &Path,
     rule_idx: usize,
     watch_idx: usize,
+    verdict: &ScriptVerdict,
 ) -> anyhow::Result<()> {
     let rule = &config.rules[rule_idx];
     let watch = &rule.watch_dirs[watch_idx];
-    let regexes = &rule.regex;
-
-    if path_matches_any_regex(src_path, regexes)? {
-        debug!("Regex matches! {:?}", src_path);
-
-        if fs::metadata(src_path)?.is_file() {
-            // For every link_dir, check if the expected link_path has a symlink, and if not,
-            // create one.
-            for link in &rule.link_dirs {
-                // error if the link_dir doesn't exist
-                anyhow::ensure!(
-                    link.exists(),
-                    "link ({:?}) does not exist... was it deleted?",
-                    link
-                );
+    // the rule's script may choose another name for the links
+    let link_name = match verdict {
+        ScriptVerdict::LinkAs(name) => Some(name),
+        ScriptVerdict::Accept | ScriptVerdict::Reject => None,
+    };
 
-                // where the symlink_path should be
-                let symlink_path = calc_link_from_src_orig(src_path, watch, link)?;
+    for link in &rule.link_dirs {
+        // error if the link_dir doesn't exist
+        anyhow::ensure!(
+            link.exists(),
+            "link ({:?}) does not exist... was it deleted?",
+            link
+        );
 
-                // try symlinking
-                try_symlinking(&symlink_path, src_path)?;
-            }
+        // where the symlink_path should be
+        let expected_path = match &link_name {
+            Some(name) => link.join(name),
+            None => calc_link_from_src_orig(src_path, watch, link)?,
+        };
+        // what a symlink already there points to, in case it's broken and gets replaced
+        let old_src_path = fs::read_link(&expected_path).ok();
+
+        // try symlinking, and remember where it ended up
+        let (symlink_path, created) = try_symlinking(&expected_path, src_path)?;
+        state.insert_link(&symlink_path, rule_idx, src_path);
+        if !created {
+            continue;
         }
+        let event = match old_src_path {
+            Some(old_src_path) if symlink_path == expected_path => {
+                Event::link_retargeted(&rule.name, &old_src_path, src_path, &symlink_path)
+            }
+            Some(_) => {
+                state.publish(
+                    config,
+                    rule_idx,
+                    Event::conflict(&rule.name, src_path, &expected_path, Some(&symlink_path)),
+                );
+                Event::link_created(&rule.name, src_path, &symlink_path)
+            }
+            None => Event::link_created(&rule.name, src_path, &symlink_path),
+        };
+        state.publish(config, rule_idx, event);
     }
 
     Ok(())
@@ -94,21 +360,24 @@ pub fn symlink_create(
 ///
 /// If a symlink already exists at symlink_path, `validate_existing_symlink`.
 /// Otherwise, create a symlink.
-fn try_symlinking(symlink_path: &Path, src_path: &Path) -> anyhow::Result<()> {
-    // check if a file exists here
-    if symlink_path.exists() {
+///
+/// Returns the path of the symlink to src_path, which may differ from symlink_path, and whether
+/// it was created.
+fn try_symlinking(symlink_path: &Path, src_path: &Path) -> anyhow::Result<(PathBuf, bool)> {
+    // check if a file exists here, including a broken symlink
+    if let Ok(metadata) = fs::symlink_metadata(symlink_path) {
         // check if that file is a symlink
-        let metadata = fs::symlink_metadata(symlink_path)?;
         if metadata.file_type().is_symlink() {
             // a symlink already exists here. we expect it to point to the src_path...
             // but what if it doesn't?
-            validate_existing_symlink(symlink_path, src_path, &metadata)?;
+            validate_existing_symlink(symlink_path, src_path, &metadata)
         } else {
-            anyhow::bail!(
-                "failed to create symlink at {:?} with target {:?}. a non-symlink file already exists at symlink path.",
-                symlink_path,
-                src_path
-            )
+            Err(anyhow::Error::new(Error::Conflict(symlink_path.to_path_buf())).context(
+                format!(
+                    "failed to create symlink at {:?} with target {:?}. a non-symlink file already exists at symlink path.",
+                    symlink_path, src_path
+                ),
+            ))
         }
     } else {
         // file doesn't exist, so create a symlink to there
@@ -117,9 +386,8 @@ fn try_symlinking(symlink_path: &Path, src_path: &Path) -> anyhow::Result<()> {
         symlink(src_path, symlink_path).with_context(|| {
             format!("creating symlink from {:?} to {:?}", symlink_path, src_path)
         })?;
+        Ok((symlink_path.to_path_buf(), true))
     }
-
-    Ok(())
 }
 
 /// Validate that the existing symlink works and points to the correct target.
@@ -127,16 +395,20 @@ fn try_symlinking(symlink_path: &Path, src_path: &Path) -> anyhow::Result<()> {
 /// If the symlink is broken, delete it.
 /// If it doesn't point to the correct target, modify the filename slightly and try again by
 /// running `try_symlinking` (recursive).
+///
+/// Returns the path of the symlink to src_path, and whether it was created.
 pub fn validate_existing_symlink(
     symlink_path: &Path,
     src_path: &Path,
     metadata: &Metadata,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<(PathBuf, bool)> {
     match symlink_target(symlink_path)? {
         // symlink is broken
         None => {
             debug!("Symlink is broken, deleting symlink: {:?}", symlink_path);
             delete_symlink(symlink_path, metadata)?;
+            // try symlinking again, now that the path is free (recursive)
+            try_symlinking(symlink_path, src_path)
         }
         // symlink target exists
         Some(symlink_target) => {
@@ -147,6 +419,7 @@ pub fn validate_existing_symlink(
                     "Symlink points to the correct source file! {:?}, {:?}, {:?}",
                     src_path, symlink_target, symlink_path
                 );
+                Ok((symlink_path.to_path_buf(), false))
             } else {
                 // doesn't point to target...
                 debug!(
@@ -155,17 +428,15 @@ pub fn validate_existing_symlink(
                 );
                 // rename by prepending with "0_"
                 let renamed_symlink_path =
-                    symlink_target.with_file_name(format!("0_{}", get_basename(symlink_path)?));
+                    symlink_path.with_file_name(format!("0_{}", get_basename(symlink_path)?));
                 debug!(
                     "renamed symlink path {:?} to {:?}",
                     symlink_path, renamed_symlink_path
                 );
 
                 // try symlinking again... (recursive)
-                try_symlinking(&renamed_symlink_path, src_path)?;
+                try_symlinking(&renamed_symlink_path, src_path)
             }
         }
-    };
-
-    Ok(())
+    }
 }
@@ -0,0 +1,421 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use tracing::{debug, info, warn};
+
+use crate::{
+    actions::symlinking::symlink_create,
+    add_xattr_tag, delete_symlink,
+    events::Event,
+    get_basename, get_match_target, path_is_under_any_dirs, remove_xattr_tag, replace_match_target,
+    rule_matches,
+    state::State,
+    symlink_target, watch_idx_for_path,
+    workers::link_watcher::{LinkDirEvent, LinkDirEventKind},
+    Config, MatchInput, MatchOn, Matcher, Rule,
+};
+
+/// Handle a change made to a link_dir by the user.
+/// Called from the Receiver.
+///
+/// Changes to paths that aren't managed links are ignored, which includes links that filetags
+/// deleted itself, since those are forgotten before being deleted.
+pub fn handle_link_dir_event(
+    config: &Config,
+    state: &mut State,
+    event: &LinkDirEvent,
+) -> anyhow::Result<()> {
+    debug!("Received new link_dir event!: {:?}", event);
+    match &event.kind {
+        LinkDirEventKind::Removed(symlink_path) => {
+            let Some(link) = state.remove_link(symlink_path) else {
+                debug!("Not a managed link, ignoring: {:?}", symlink_path);
+                return Ok(());
+            };
+            if config.rules[link.rule_idx].settings.untag_on_link_delete {
+                untag_source(config, Some(state), link.rule_idx, &link.src_path)
+                    .context("untagging source of deleted link")?;
+            }
+        }
+        LinkDirEventKind::Renamed { from, to } => {
+            let Some(link) = state.remove_link(from) else {
+                debug!("Not a managed link, ignoring: {:?}", from);
+                return Ok(());
+            };
+            state.insert_link(to, link.rule_idx, &link.src_path);
+            if config.rules[link.rule_idx].settings.propagate_link_renames {
+                rename_source_after_link(config, state, link.rule_idx, &link.src_path, to)
+                    .context("renaming source of renamed link")?;
+            }
+        }
+        LinkDirEventKind::Created { path, tracker } => {
+            if state.links.contains_key(path) {
+                debug!("Managed link, ignoring: {:?}", path);
+                return Ok(());
+            }
+            if config.rules[event.rule_idx].settings.tag_on_link_drop {
+                tag_dropped_path(config, state, event.rule_idx, path, *tracker)
+                    .context("tagging source of dropped path")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Renames a source to match the new name of its link, then replaces the link with a managed
+/// link to the renamed source.
+///
+/// If the new name lost the rule's tag (no regex matches the renamed source, on what
+/// `match_on` chooses), the rule's `cookie` is prepended to keep the source tagged. If a file
+/// already exists at the new source name, the conflict is logged and published, and nothing is
+/// renamed.
+fn rename_source_after_link(
+    config: &Config,
+    state: &mut State,
+    rule_idx: usize,
+    src_path: &Path,
+    symlink_path: &Path,
+) -> anyhow::Result<()> {
+    let rule = &config.rules[rule_idx];
+    let Some(watch_idx) = watch_idx_for_path(config, rule_idx, src_path) else {
+        return Ok(());
+    };
+
+    let mut renamed_src_path = src_path.with_file_name(get_basename(symlink_path)?);
+    let input = MatchInput::new(
+        &renamed_src_path,
+        &rule.watch_dirs[watch_idx],
+        rule.settings.match_on,
+    );
+    if !rule.regex.is_empty() && rule.regex.matches(&input)?.is_none() {
+        if let Some(cookie) = &rule.cookie {
+            renamed_src_path =
+                src_path.with_file_name(format!("{}{}", cookie, get_basename(symlink_path)?));
+        }
+    }
+
+    if renamed_src_path == src_path {
+        return Ok(());
+    }
+    if renamed_src_path.symlink_metadata().is_ok() {
+        warn!(
+            "conflict: can't rename {:?} after its link, {:?} already exists",
+            src_path, renamed_src_path
+        );
+        state.publish(
+            config,
+            rule_idx,
+            Event::conflict(&rule.name, src_path, &renamed_src_path, None),
+        );
+        return Ok(());
+    }
+
+    info!(
+        "renaming {:?} to {:?} after its link {:?}",
+        src_path, renamed_src_path, symlink_path
+    );
+    fs::rename(src_path, &renamed_src_path)
+        .with_context(|| format!("renaming {:?} to {:?}", src_path, renamed_src_path))?;
+
+    // the renamed link now points to nothing, replace it
+    if let Ok(metadata) = fs::symlink_metadata(symlink_path) {
+        state.remove_link(symlink_path);
+        delete_symlink(symlink_path, &metadata)?;
+        state.publish(
+            config,
+            rule_idx,
+            Event::link_removed(&rule.name, src_path, symlink_path),
+        );
+    }
+    match watch_idx_for_path(config, rule_idx, &renamed_src_path) {
+        Some(watch_idx) => symlink_create(config, state, &renamed_src_path, rule_idx, watch_idx),
+        None => Ok(()),
+    }
+}
+
+/// Tags the source of a path dropped into a link_dir, then replaces it with a managed link.
+///
+/// A dropped symlink's source is its target, which must be under one of the rule's watch_dirs.
+/// It's only deleted once its source is a member, and otherwise left in place and published as
+/// a conflict. A file moved into the link_dir is first returned to where it came from, if
+/// that's known.
+fn tag_dropped_path(
+    config: &Config,
+    state: &mut State,
+    rule_idx: usize,
+    path: &Path,
+    tracker: Option<usize>,
+) -> anyhow::Result<()> {
+    let rule = &config.rules[rule_idx];
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        debug!("Dropped path no longer exists: {:?}", path);
+        return Ok(());
+    };
+
+    let src_path = if metadata.file_type().is_symlink() {
+        let Some(target_path) = symlink_target(path)? else {
+            debug!(
+                "Dropped symlink is broken, leaving it to the cleaner: {:?}",
+                path
+            );
+            return Ok(());
+        };
+        if !path_is_under_any_dirs(&target_path, &rule.watch_dirs)? {
+            debug!(
+                "Dropped symlink isn't to a watch_dir, leaving it to the cleaner: {:?}",
+                path
+            );
+            return Ok(());
+        }
+        target_path
+    } else {
+        let Some(origin_path) = tracker.and_then(|t| state.take_recent_move(t)) else {
+            warn!("don't know where {:?} was moved from, leaving it", path);
+            return Ok(());
+        };
+        if !path_is_under_any_dirs(&origin_path, &rule.watch_dirs)? {
+            warn!("{:?} wasn't moved from a watch_dir, leaving it", path);
+            return Ok(());
+        }
+        if origin_path.symlink_metadata().is_ok() {
+            warn!(
+                "conflict: can't return {:?}, {:?} already exists",
+                path, origin_path
+            );
+            state.publish(
+                config,
+                rule_idx,
+                Event::conflict(&rule.name, path, &origin_path, None),
+            );
+            return Ok(());
+        }
+        info!("returning {:?} to {:?}", path, origin_path);
+        fs::rename(path, &origin_path)
+            .with_context(|| format!("renaming {:?} to {:?}", path, origin_path))?;
+        origin_path
+    };
+
+    let src_path =
+        tag_source(config, Some(state), rule_idx, &src_path).context("tagging source")?;
+    if metadata.file_type().is_symlink() {
+        if !rule_matches(config, rule_idx, &src_path)? {
+            warn!(
+                "conflict: couldn't tag {:?}, leaving its dropped link {:?}",
+                src_path, path
+            );
+            state.publish(
+                config,
+                rule_idx,
+                Event::conflict(&rule.name, &src_path, path, None),
+            );
+            return Ok(());
+        }
+        // it's replaced with a managed link below
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            delete_symlink(path, &metadata)?;
+        }
+    }
+    match watch_idx_for_path(config, rule_idx, &src_path) {
+        Some(watch_idx) => symlink_create(config, state, &src_path, rule_idx, watch_idx),
+        None => Ok(()),
+    }
+}
+
+/// Tags a source, so that it becomes a member of the rule. Returns its new path.
+///
+/// The rule's `cookie` is prepended to its name, and the rule's `xattr_tag` is added to its
+/// xattrs. If a file already exists at the tagged name, the conflict is logged (and published,
+/// if `state` is given), and nothing is renamed.
+pub fn tag_source(
+    config: &Config,
+    state: Option<&mut State>,
+    rule_idx: usize,
+    src_path: &Path,
+) -> anyhow::Result<PathBuf> {
+    let rule = &config.rules[rule_idx];
+    if rule_matches(config, rule_idx, src_path)? {
+        return Ok(src_path.to_path_buf());
+    }
+
+    if let Some(tag) = &rule.xattr_tag {
+        add_xattr_tag(src_path, &rule.settings.xattr_name, tag)?;
+    }
+
+    let mut tagged_path = src_path.to_path_buf();
+    if let Some(cookie) = &rule.cookie {
+        let renamed_path =
+            src_path.with_file_name(format!("{}{}", cookie, get_basename(src_path)?));
+        if renamed_path.symlink_metadata().is_ok() {
+            warn!(
+                "conflict: can't tag {:?}, {:?} already exists",
+                src_path, renamed_path
+            );
+            if let Some(state) = state {
+                state.publish(
+                    config,
+                    rule_idx,
+                    Event::conflict(&rule.name, src_path, &renamed_path, None),
+                );
+            }
+            return Ok(tagged_path);
+        }
+        info!("tagging {:?} to {:?}", src_path, renamed_path);
+        fs::rename(src_path, &renamed_path)
+            .with_context(|| format!("renaming {:?} to {:?}", src_path, renamed_path))?;
+        tagged_path = renamed_path;
+    }
+
+    if !rule_matches(config, rule_idx, &tagged_path)? {
+        warn!(
+            "{:?} still isn't a member of rule {:?} after tagging, is `cookie` set?",
+            tagged_path, rule.name
+        );
+    }
+    Ok(tagged_path)
+}
+
+/// Untags a source, so that it's no longer a member of the rule.
+///
+/// The part of its name matched by the rule's regex (on what `match_on` chooses) is replaced
+/// with `untag_replace` (stripped by default), and the rule's `xattr_tag` is removed from its
+/// xattrs. Untagging never moves it to another directory. Tags inherited from a
+/// parent directory or declared in sidecar files are left alone.
+///
+/// If a file already exists at the untagged name, the conflict is logged (and published, if
+/// `state` is given), and nothing is renamed.
+pub fn untag_source(
+    config: &Config,
+    state: Option<&mut State>,
+    rule_idx: usize,
+    src_path: &Path,
+) -> anyhow::Result<()> {
+    let rule = &config.rules[rule_idx];
+    if !src_path.try_exists()? {
+        debug!("Source no longer exists, not untagging: {:?}", src_path);
+        return Ok(());
+    }
+
+    if let Some(tag) = &rule.xattr_tag {
+        remove_xattr_tag(src_path, &rule.settings.xattr_name, tag)?;
+    }
+
+    let Some(watch_idx) = watch_idx_for_path(config, rule_idx, src_path) else {
+        return Ok(());
+    };
+    let watch_dir = &rule.watch_dirs[watch_idx];
+    let input = MatchInput::new(src_path, watch_dir, rule.settings.match_on);
+    if rule.regex.matches(&input)?.is_none() {
+        return Ok(());
+    }
+    let Some(untagged_path) = untagged_path(rule, src_path, watch_dir) else {
+        warn!(
+            "untagging {:?} would leave it with an empty or unchanged name, or move it, not \
+             renaming",
+            src_path
+        );
+        return Ok(());
+    };
+
+    if untagged_path.symlink_metadata().is_ok() {
+        warn!(
+            "conflict: can't untag {:?}, {:?} already exists",
+            src_path, untagged_path
+        );
+        if let Some(state) = state {
+            state.publish(
+                config,
+                rule_idx,
+                Event::conflict(&rule.name, src_path, &untagged_path, None),
+            );
+        }
+        return Ok(());
+    }
+
+    info!("untagging {:?} to {:?}", src_path, untagged_path);
+    fs::rename(src_path, &untagged_path)
+        .with_context(|| format!("renaming {:?} to {:?}", src_path, untagged_path))
+}
+
+/// Returns the path a source would have once untagged (see `untag_source`): the part chosen by
+/// the rule's `match_on` has what its regex matches replaced. None if no regex matches it, or
+/// untagging would leave its name empty or unchanged, or move it to another directory.
+pub fn untagged_path(rule: &Rule, src_path: &Path, watch_dir: &Path) -> Option<PathBuf> {
+    let match_on = rule.settings.match_on;
+    let target = get_match_target(src_path, match_on, watch_dir).ok()?;
+    let regex = rule.regex.iter().find(|r| r.is_match(target))?;
+    let untagged = regex.replace(target, rule.untag_replace.as_deref().unwrap_or(""));
+    // an extension can be stripped entirely
+    if untagged == target || (untagged.is_empty() && match_on != MatchOn::Extension) {
+        return None;
+    }
+    let untagged_path = replace_match_target(src_path, match_on, watch_dir, &untagged)?;
+    (untagged_path.parent() == src_path.parent() && untagged_path.file_name().is_some())
+        .then_some(untagged_path)
+}
+
+/// Adds the rule's tag to the xattrs of a source that was just linked, if the rule has
+/// `write_xattr_tags` set, so that file managers see the same tags as filetags. Sources already
+/// written to are skipped.
+///
+/// Failures (e.g. read-only media) are logged rather than returned.
+pub fn write_source_tag(config: &Config, state: &mut State, rule_idx: usize, src_path: &Path) {
+    let rule = &config.rules[rule_idx];
+    let key = (rule_idx, src_path.to_path_buf());
+    if !rule.settings.write_xattr_tags || state.written_tags.contains_key(&key) {
+        return;
+    }
+
+    debug!(
+        "Writing tag {:?} to source: {:?}",
+        rule.write_tag(),
+        src_path
+    );
+    let added = match add_xattr_tag(src_path, &rule.settings.xattr_name, rule.write_tag()) {
+        Ok(added) => added,
+        Err(e) => {
+            warn!("failed to write tag to source {:?}: {:#}", src_path, e);
+            false
+        }
+    };
+    state.written_tags.insert(key, added);
+}
+
+/// Removes the rule's tag from the xattrs of a source whose link was just deleted, if the rule
+/// has `write_xattr_tags` set and the source is no longer a member of the rule.
+///
+/// Only a tag that filetags added itself while running is removed, so that one set by the user
+/// is left alone, as is one written before a restart.
+///
+/// Failures (e.g. read-only media) are logged rather than returned.
+pub fn remove_source_tag(config: &Config, state: &mut State, rule_idx: usize, src_path: &Path) {
+    let rule = &config.rules[rule_idx];
+    if !rule.settings.write_xattr_tags {
+        return;
+    }
+    let exists = src_path.exists();
+    // still a member, e.g. the link was only deleted for being inside a tagged dir
+    if exists && rule_matches(config, rule_idx, src_path).unwrap_or(false) {
+        return;
+    }
+    // a file created at the path later has to be written to again
+    let added = state
+        .written_tags
+        .remove(&(rule_idx, src_path.to_path_buf()))
+        .unwrap_or(false);
+    if !exists || !added {
+        return;
+    }
+
+    debug!(
+        "Removing tag {:?} from source: {:?}",
+        rule.write_tag(),
+        src_path
+    );
+    if let Err(e) = remove_xattr_tag(src_path, &rule.settings.xattr_name, rule.write_tag()) {
+        warn!("failed to remove tag from source {:?}: {:#}", src_path, e);
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// How many events a subscriber may fall behind before it misses some.
+const EVENTS_CAPACITY: usize = 1024;
+
+/// How many recent events are kept for subscribers that ask for them, e.g. `filetags events`.
+const RECENT_EVENTS_CAPACITY: usize = 256;
+
+// Event //////////////////////////////////////////////////////////////////////
+
+/// Something the responder did, published for subscribers (see `Dispatcher::subscribe`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A symlink to a member was created.
+    LinkCreated {
+        rule: String,
+        src_path: PathBuf,
+        link_path: PathBuf,
+    },
+    /// A symlink was deleted. `src_path` is its target, which may no longer exist.
+    LinkRemoved {
+        rule: String,
+        src_path: PathBuf,
+        link_path: PathBuf,
+    },
+    /// A broken symlink was replaced with one to a new source, at the same path.
+    LinkRetargeted {
+        rule: String,
+        old_src_path: PathBuf,
+        src_path: PathBuf,
+        link_path: PathBuf,
+    },
+    /// A link or source couldn't be given its name, because `conflict_path` is taken.
+    /// `link_path` is the link created instead, if any.
+    Conflict {
+        rule: String,
+        src_path: PathBuf,
+        conflict_path: PathBuf,
+        link_path: Option<PathBuf>,
+    },
+    /// A full scan of the watch_dirs started.
+    ScanStarted,
+    /// A full scan of the watch_dirs finished, with this many managed links.
+    ScanFinished { links: usize },
+    /// Handling a message failed, which stops the responder unless it was a request.
+    Error { message: String },
+}
+
+impl Event {
+    pub fn link_created(rule: &str, src_path: &Path, link_path: &Path) -> Self {
+        Event::LinkCreated {
+            rule: rule.to_string(),
+            src_path: src_path.to_path_buf(),
+            link_path: link_path.to_path_buf(),
+        }
+    }
+
+    pub fn link_removed(rule: &str, src_path: &Path, link_path: &Path) -> Self {
+        Event::LinkRemoved {
+            rule: rule.to_string(),
+            src_path: src_path.to_path_buf(),
+            link_path: link_path.to_path_buf(),
+        }
+    }
+
+    pub fn link_retargeted(
+        rule: &str,
+        old_src_path: &Path,
+        src_path: &Path,
+        link_path: &Path,
+    ) -> Self {
+        Event::LinkRetargeted {
+            rule: rule.to_string(),
+            old_src_path: old_src_path.to_path_buf(),
+            src_path: src_path.to_path_buf(),
+            link_path: link_path.to_path_buf(),
+        }
+    }
+
+    pub fn conflict(
+        rule: &str,
+        src_path: &Path,
+        conflict_path: &Path,
+        link_path: Option<&Path>,
+    ) -> Self {
+        Event::Conflict {
+            rule: rule.to_string(),
+            src_path: src_path.to_path_buf(),
+            conflict_path: conflict_path.to_path_buf(),
+            link_path: link_path.map(Path::to_path_buf),
+        }
+    }
+}
+
+// EventBus ///////////////////////////////////////////////////////////////////
+
+/// Publishes events to every subscriber, and keeps the most recent ones.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+    recent: Arc<Mutex<VecDeque<Event>>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(EVENTS_CAPACITY).0,
+            recent: Arc::default(),
+        }
+    }
+}
+
+impl EventBus {
+    /// Publishes an event. Having no subscribers isn't an error.
+    pub fn publish(&self, event: Event) {
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.len() == RECENT_EVENTS_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(event.clone());
+        let _ = self.tx.send(event);
+    }
+
+    /// Subscribes to events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+
+    /// Subscribes to events published from now on, and returns the recent ones, so that none
+    /// are missed or seen twice in between.
+    pub fn subscribe_with_recent(&self) -> (Vec<Event>, broadcast::Receiver<Event>) {
+        let recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        (recent.iter().cloned().collect(), self.tx.subscribe())
+    }
+}
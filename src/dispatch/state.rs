@@ -0,0 +1,206 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use smart_default::SmartDefault;
+
+use crate::{
+    actions::ActionReport,
+    events::{Event, EventBus},
+    workers::hook_runner::{start_hook_runner, HookRunner},
+    Config, HashCache, TagIndex,
+};
+
+/// How many recently moved paths to remember.
+const RECENT_MOVES_CAPACITY: usize = 64;
+
+// State //////////////////////////////////////////////////////////////////////
+
+/// Mutable state owned by the responder, and passed to the actions it invokes.
+#[derive(Debug, SmartDefault)]
+pub struct State {
+    /// When the responder started.
+    #[default(SystemTime::now())]
+    pub started: SystemTime,
+
+    /// Every symlink created or validated by filetags, keyed by its path.
+    pub links: HashMap<PathBuf, ManagedLink>,
+
+    /// The paths of the managed symlinks pointing to each source, so that the links into a
+    /// directory can be found without walking the link_dirs. Kept in step with `links`.
+    pub sources: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+
+    /// Paths recently moved away from a watch_dir, keyed by their Notify rename tracker,
+    /// so that a file moved into a link_dir can be returned to where it came from.
+    pub recent_moves: VecDeque<(usize, PathBuf)>,
+
+    /// The content of tagged files, if any rule has `remember_tags` set.
+    pub tag_index: Option<TagIndex>,
+
+    /// Whether the tag index changed since it was last saved.
+    pub dirty_index: bool,
+
+    /// The content keys of sources, for rules with `dedupe` set.
+    pub hashes: HashCache,
+
+    /// The sources whose xattrs have had the rule's tag written to them, by rule index, so that
+    /// they aren't read again every time the source is rescanned. Each is mapped to whether
+    /// filetags added the tag, rather than finding it set already, as only those are removed.
+    pub written_tags: HashMap<(usize, PathBuf), bool>,
+
+    /// The members listed in the playlists of each rule, keyed by rule index.
+    pub playlist_members: HashMap<usize, BTreeSet<PathBuf>>,
+
+    /// The rules whose playlists need to be written again.
+    pub dirty_playlists: BTreeSet<usize>,
+
+    /// Runs the hook commands of rules, if any rule has one.
+    pub hooks: Option<HookRunner>,
+
+    /// Where events are published for subscribers, shared with the Dispatcher.
+    pub events: EventBus,
+
+    /// What the requested action being handled has done so far, if any.
+    pub report: Option<ActionReport>,
+}
+
+/// A symlink managed by filetags.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagedLink {
+    pub rule_idx: usize,
+    pub src_path: PathBuf,
+}
+
+impl State {
+    /// Creates the state for a config, loading the tag index and starting the hook runner if
+    /// any rule needs them. Must be called from within the Tokio runtime.
+    pub fn new(config: &Config, events: EventBus) -> anyhow::Result<Self> {
+        let tag_index = match config.rules.iter().any(|r| r.settings.remember_tags) {
+            true => Some(TagIndex::load(&config.misc.index_path).context("loading tag index")?),
+            false => None,
+        };
+
+        // write every playlist at least once, even if it stays empty
+        let dirty_playlists = (0..config.rules.len())
+            .filter(|idx| !config.rules[*idx].playlists.is_empty())
+            .collect();
+
+        Ok(Self {
+            tag_index,
+            dirty_playlists,
+            hooks: start_hook_runner(config),
+            events,
+            ..Self::default()
+        })
+    }
+
+    /// Publishes an event about the rule, and queues the rule's hook command for it, if it has
+    /// one. It's also recorded in the report of the requested action being handled, if any.
+    pub fn publish(&mut self, config: &Config, rule_idx: usize, event: Event) {
+        if let Some(hooks) = &self.hooks {
+            hooks.fire(config, rule_idx, &event);
+        }
+        if let Some(report) = &mut self.report {
+            report.record(&event);
+        }
+        self.events.publish(event);
+    }
+
+    /// Records a source that matched a rule but won't be linked, in the report of the requested
+    /// action being handled, if any.
+    pub fn skip(&mut self, src_path: &Path) {
+        if let Some(report) = &mut self.report {
+            report.skipped.push(src_path.to_path_buf());
+        }
+    }
+
+    /// Returns whether the symlink at the path was created by something other than filetags
+    /// while it was running, i.e. it's unmanaged and newer than the responder.
+    pub fn is_foreign_link(&self, symlink_path: &Path, metadata: &Metadata) -> bool {
+        !self.links.contains_key(symlink_path)
+            && metadata
+                .modified()
+                .is_ok_and(|modified| modified >= self.started)
+    }
+
+    /// Records a symlink as managed.
+    pub fn insert_link(&mut self, symlink_path: &Path, rule_idx: usize, src_path: &Path) {
+        self.remove_link(symlink_path);
+        self.links.insert(
+            symlink_path.to_path_buf(),
+            ManagedLink {
+                rule_idx,
+                src_path: src_path.to_path_buf(),
+            },
+        );
+        self.sources
+            .entry(src_path.to_path_buf())
+            .or_default()
+            .insert(symlink_path.to_path_buf());
+    }
+
+    /// Returns the paths of the managed symlinks of the rule that point to the source.
+    pub fn links_to(&self, rule_idx: usize, src_path: &Path) -> Vec<PathBuf> {
+        let Some(symlink_paths) = self.sources.get(src_path) else {
+            return vec![];
+        };
+        symlink_paths
+            .iter()
+            .filter(|symlink_path| {
+                self.links
+                    .get(*symlink_path)
+                    .is_some_and(|link| link.rule_idx == rule_idx)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the managed symlinks pointing to the path or anything inside it, of any rule.
+    pub fn links_under(&self, path: &Path) -> Vec<(PathBuf, ManagedLink)> {
+        self.sources
+            .range(path.to_path_buf()..)
+            .take_while(|(src_path, _)| src_path.starts_with(path))
+            .flat_map(|(_, symlink_paths)| symlink_paths)
+            .filter_map(|symlink_path| {
+                let link = self.links.get(symlink_path)?;
+                Some((symlink_path.clone(), link.clone()))
+            })
+            .collect()
+    }
+
+    /// Forgets a managed symlink, returning what it pointed to.
+    pub fn remove_link(&mut self, symlink_path: &Path) -> Option<ManagedLink> {
+        let link = self.links.remove(symlink_path)?;
+        if let Some(symlink_paths) = self.sources.get_mut(&link.src_path) {
+            symlink_paths.remove(symlink_path);
+            if symlink_paths.is_empty() {
+                self.sources.remove(&link.src_path);
+            }
+        }
+        Some(link)
+    }
+
+    /// Remembers where a moved path came from.
+    pub fn insert_recent_move(&mut self, tracker: usize, from_path: &Path) {
+        if self.recent_moves.len() == RECENT_MOVES_CAPACITY {
+            self.recent_moves.pop_front();
+        }
+        self.recent_moves
+            .push_back((tracker, from_path.to_path_buf()));
+    }
+
+    /// Returns whether the move with the given tracker came from a watch_dir.
+    pub fn has_recent_move(&self, tracker: usize) -> bool {
+        self.recent_moves.iter().any(|(t, _)| *t == tracker)
+    }
+
+    /// Takes the path that the move with the given tracker came from, if it's remembered.
+    pub fn take_recent_move(&mut self, tracker: usize) -> Option<PathBuf> {
+        let idx = self.recent_moves.iter().position(|(t, _)| *t == tracker)?;
+        self.recent_moves.remove(idx).map(|(_, path)| path)
+    }
+}
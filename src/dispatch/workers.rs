@@ -1,3 +1,7 @@
+pub mod event_server;
+pub mod glob_watcher;
+pub mod hook_runner;
+pub mod link_watcher;
 pub mod periodic_cleaner;
 pub mod watcher;
 
@@ -5,4 +9,9 @@ pub mod watcher;
 pub enum WorkerType {
     Cleaners,
     Watchers,
+    /// Keeps the watch_dirs matched by globs, and their watchers, up to date as directories
+    /// matching them appear and disappear.
+    GlobWatchers,
+    LinkWatchers,
+    EventServer,
 }
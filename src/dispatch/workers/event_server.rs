@@ -0,0 +1,130 @@
+use std::{fs, os::unix::fs::FileTypeExt, path::Path};
+
+use anyhow::Context;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::broadcast::error::RecvError,
+    task::JoinHandle,
+};
+use tracing::{debug, warn};
+
+use crate::{
+    events::{Event, EventBus},
+    Config,
+};
+
+/// Start serving events as newline-delimited JSON on the `event_socket`, for `filetags events`.
+///
+/// A client writes `recent` or `follow` on a line. It's sent the recent events, then, if
+/// following, every new event until it disconnects.
+///
+/// Nothing is started unless `event_socket` is set. If another daemon is already serving on the
+/// socket, or it can't be bound, this is logged and nothing is started either, as events aren't
+/// needed for linking.
+pub fn start_event_server(
+    events: &EventBus,
+    config: &Config,
+) -> anyhow::Result<Vec<JoinHandle<anyhow::Result<()>>>> {
+    let Some(socket_path) = &config.misc.event_socket else {
+        debug!("No event_socket is set, not serving events");
+        return Ok(vec![]);
+    };
+    let listener = match bind_socket(socket_path) {
+        Ok(Some(listener)) => listener,
+        Ok(None) => {
+            warn!(
+                "another daemon is serving events at {:?}, not serving them",
+                socket_path
+            );
+            return Ok(vec![]);
+        }
+        Err(e) => {
+            warn!("not serving events at {:?}: {:#}", socket_path, e);
+            return Ok(vec![]);
+        }
+    };
+
+    let events = events.clone();
+    Ok(vec![tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.context("accepting client")?;
+            let events = events.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_client(stream, events).await {
+                    debug!("Event client disconnected: {:#}", e);
+                }
+            });
+        }
+    })])
+}
+
+/// Binds the socket, replacing one left behind by a daemon that's no longer running.
+/// Returns None if a daemon is still serving on it.
+fn bind_socket(socket_path: &Path) -> anyhow::Result<Option<UnixListener>> {
+    if let Ok(metadata) = socket_path.symlink_metadata() {
+        if std::os::unix::net::UnixStream::connect(socket_path).is_ok() {
+            return Ok(None);
+        }
+        anyhow::ensure!(
+            metadata.file_type().is_socket(),
+            "a non-socket file already exists there"
+        );
+        fs::remove_file(socket_path).context("removing stale socket")?;
+    }
+    if let Some(parent) = socket_path.parent() {
+        fs::create_dir_all(parent).context("creating parent dirs")?;
+    }
+
+    Ok(Some(
+        UnixListener::bind(socket_path).context("binding socket")?,
+    ))
+}
+
+/// Serves a single client, see `start_event_server`.
+async fn serve_client(stream: UnixStream, events: EventBus) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut request = String::new();
+    reader
+        .read_line(&mut request)
+        .await
+        .context("reading request")?;
+    let follow = match request.trim() {
+        "recent" => false,
+        "follow" => true,
+        other => anyhow::bail!("unknown request: {:?}", other),
+    };
+
+    let (recent, mut rx) = events.subscribe_with_recent();
+    for event in &recent {
+        write_event(&mut writer, event).await?;
+    }
+    if !follow {
+        return Ok(());
+    }
+
+    let mut buf = [0; 64];
+    loop {
+        tokio::select! {
+            received = rx.recv() => match received {
+                Ok(event) => write_event(&mut writer, &event).await?,
+                Err(RecvError::Lagged(missed)) => {
+                    let message = format!("client fell behind, missed {} events", missed);
+                    write_event(&mut writer, &Event::Error { message }).await?
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            // anything else the client writes is ignored, until it disconnects
+            read = reader.read(&mut buf) => if read.context("reading from client")? == 0 {
+                return Ok(());
+            },
+        }
+    }
+}
+
+async fn write_event(writer: &mut (impl AsyncWrite + Unpin), event: &Event) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(event).context("serializing event")?;
+    line.push(b'\n');
+    writer.write_all(&line).await.context("writing event")
+}
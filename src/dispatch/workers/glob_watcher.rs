@@ -0,0 +1,250 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use notify::{event::ModifyKind, Event, EventKind, INotifyWatcher, RecursiveMode, Watcher};
+use tokio::{sync::mpsc::UnboundedReceiver, task::JoinHandle};
+use tracing::{debug, info};
+
+use crate::{
+    actions::Action, glob_dirs, is_glob, workers::watcher::create_watcher_closure, Config, Message,
+    Sender,
+};
+
+/// How long to let a burst of changes settle (e.g. a directory tree being copied into place)
+/// before evaluating the globs again.
+const SETTLE_DELAY: Duration = Duration::from_millis(100);
+
+/// Create and start the worker that keeps the watch_dirs matched by globs up to date, if any
+/// rule has one.
+pub fn start_glob_watchers(
+    tx: &Sender<Message>,
+    config: &Arc<Config>,
+) -> anyhow::Result<Vec<JoinHandle<anyhow::Result<()>>>> {
+    if !config.rules.iter().any(|rule| rule.has_watch_globs()) {
+        return Ok(vec![]);
+    }
+
+    let (change_tx, change_rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+    let parent_watcher = notify::recommended_watcher(move |res: Result<Event, _>| match res {
+        Ok(event) => {
+            if matches!(
+                event.kind,
+                EventKind::Create(_)
+                    | EventKind::Remove(_)
+                    | EventKind::Modify(ModifyKind::Name(_))
+            ) {
+                let _ = change_tx.send(());
+            }
+        }
+        Err(e) => debug!("GLOB WATCH ERROR! {}", e),
+    })
+    .context("creating notify watcher")?;
+    let mut globs = GlobWatcher {
+        tx: tx.clone(),
+        config: Arc::clone(config),
+        watch_dirs: config.rules.iter().map(|r| r.watch_dirs.clone()).collect(),
+        found: HashMap::new(),
+        parent_watcher,
+        parents: HashMap::new(),
+    };
+
+    // the directories matched so far are watched once launched
+    globs.settle(true)?;
+
+    Ok(vec![tokio::spawn(globs.run(change_rx))])
+}
+
+/// Watches the directories in which new directories could match the watch_dir globs, and
+/// evaluates the globs again whenever something is created, removed or renamed in them.
+///
+/// A directory that starts matching is added to the rule's watch_dirs (`Action::AddWatchDir`)
+/// and gets a watcher. One that stops matching, because it's gone, has its watcher retired,
+/// and the rule's link_dirs cleaned of the links into it.
+struct GlobWatcher {
+    tx: Sender<Message>,
+    config: Arc<Config>,
+    /// The watch_dirs of every rule as the responder has them, as it's sent the same
+    /// `AddWatchDir` actions in the same order.
+    watch_dirs: Vec<Vec<PathBuf>>,
+    /// The watch_dirs currently matched by globs, by rule index and path, with their watchers.
+    /// Query rules have no watchers.
+    found: HashMap<(usize, PathBuf), Option<JoinHandle<anyhow::Result<()>>>>,
+    parent_watcher: INotifyWatcher,
+    /// The directories watched by `parent_watcher`, and whether they're watched recursively.
+    parents: HashMap<PathBuf, bool>,
+}
+
+impl GlobWatcher {
+    async fn run(mut self, mut changes: UnboundedReceiver<()>) -> anyhow::Result<()> {
+        loop {
+            if changes.recv().await.is_none() {
+                return Ok(());
+            }
+            tokio::time::sleep(SETTLE_DELAY).await;
+            while changes.try_recv().is_ok() {}
+
+            self.settle(false)?;
+        }
+    }
+
+    /// Updates the found watch_dirs and the watched parents until they agree, since matching
+    /// directories may have appeared before their parents were watched.
+    fn settle(&mut self, first: bool) -> anyhow::Result<()> {
+        self.update_found_dirs(first)?;
+        while self.update_parent_watches() {
+            self.update_found_dirs(false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates the globs of every rule, and adds and retires watch_dirs accordingly.
+    /// The first time, the directories already in the config were linked by the initial scan,
+    /// and only need watchers.
+    fn update_found_dirs(&mut self, first: bool) -> anyhow::Result<()> {
+        let config = Arc::clone(&self.config);
+        for (rule_idx, rule) in config.rules.iter().enumerate() {
+            if !rule.has_watch_globs() {
+                continue;
+            }
+            let found = rule
+                .expand_watch_patterns()
+                .into_iter()
+                .filter(|dir| !rule.watch_patterns.contains(dir))
+                .collect::<Vec<_>>();
+
+            for dir in &found {
+                if self.found.contains_key(&(rule_idx, dir.clone())) {
+                    continue;
+                }
+                let known = self.watch_dirs[rule_idx].iter().position(|d| d == dir);
+                let watch_idx = known.unwrap_or_else(|| {
+                    self.watch_dirs[rule_idx].push(dir.clone());
+                    self.watch_dirs[rule_idx].len() - 1
+                });
+                if !(first && known.is_some()) {
+                    info!(?dir, rule = rule.name, "found watch_dir");
+                    self.tx
+                        .send(Message::Action(Action::AddWatchDir(rule_idx, dir.clone())))
+                        .context("sending message AddWatchDir")?;
+                }
+                // the watcher is started after the responder is told about the watch_dir, so
+                // that its events are handled after it's added
+                let watcher = match rule.query {
+                    Some(_) => None,
+                    None => Some(tokio::spawn(create_watcher_closure(
+                        &self.tx,
+                        &config,
+                        rule_idx,
+                        watch_idx,
+                        dir.clone(),
+                    )?)),
+                };
+                self.found.insert((rule_idx, dir.clone()), watcher);
+            }
+
+            let gone = self
+                .found
+                .keys()
+                .filter(|(idx, dir)| *idx == rule_idx && !found.contains(dir))
+                .cloned()
+                .collect::<Vec<_>>();
+            if gone.is_empty() {
+                continue;
+            }
+            for key in gone {
+                info!(dir = ?key.1, rule = rule.name, "watch_dir is gone");
+                if let Some(Some(watcher)) = self.found.remove(&key) {
+                    watcher.abort();
+                }
+            }
+            // its sources are gone, so their links are broken
+            for link_idx in 0..rule.link_dirs.len() {
+                self.tx
+                    .send(Message::Action(Action::CleanDir(rule_idx, link_idx)))
+                    .context("sending message CleanDir")?;
+            }
+            if !rule.playlists.is_empty() {
+                self.tx
+                    .send(Message::Action(Action::CleanPlaylists(rule_idx)))
+                    .context("sending message CleanPlaylists")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Watches the parent directories of every glob as they are now, and stops watching those
+    /// that are gone. Returns whether any were added.
+    ///
+    /// Directories watched non-recursively are watched again each time, in case they were
+    /// removed and created again in the meantime.
+    fn update_parent_watches(&mut self) -> bool {
+        let mut parents = HashMap::new();
+        for rule in &self.config.rules {
+            for pattern in rule.watch_patterns.iter().filter(|p| is_glob(p)) {
+                parents.extend(glob_parents(pattern));
+            }
+        }
+
+        for (dir, recursive) in &self.parents {
+            if parents.get(dir) != Some(recursive) {
+                let _ = self.parent_watcher.unwatch(dir);
+            }
+        }
+        let mut added = false;
+        let mut watched = HashMap::new();
+        for (dir, recursive) in parents {
+            let known = self.parents.get(&dir) == Some(&recursive);
+            let mode = match recursive {
+                true if known => {
+                    watched.insert(dir, recursive);
+                    continue;
+                }
+                true => RecursiveMode::Recursive,
+                false => RecursiveMode::NonRecursive,
+            };
+            // the directory may be gone again already
+            match self.parent_watcher.watch(&dir, mode) {
+                Ok(()) => {
+                    added |= !known;
+                    watched.insert(dir, recursive);
+                }
+                Err(e) => debug!("failed to watch {:?} for globs: {}", dir, e),
+            }
+        }
+        self.parents = watched;
+
+        added
+    }
+}
+
+/// Returns the directories in which an entry being created could make the glob match another
+/// directory, and whether they must be watched recursively, i.e. every directory matching a
+/// part of the glob before its last component, starting with the first part that's a glob.
+/// Below a `**`, everything is watched.
+fn glob_parents(pattern: &Path) -> Vec<(PathBuf, bool)> {
+    let components = pattern.components().collect::<Vec<_>>();
+    let mut parents = vec![];
+    let mut prefix = PathBuf::new();
+    let mut globbing = false;
+    for component in components {
+        let part = Path::new(component.as_os_str());
+        globbing |= is_glob(part);
+        if globbing && !prefix.as_os_str().is_empty() {
+            let recursive = part.as_os_str() == "**";
+            parents.extend(glob_dirs(&prefix).into_iter().map(|dir| (dir, recursive)));
+            if recursive {
+                break;
+            }
+        }
+        prefix.push(part);
+    }
+
+    parents
+}
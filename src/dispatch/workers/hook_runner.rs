@@ -0,0 +1,224 @@
+use std::{fmt, process::Stdio, sync::Arc, time::Duration};
+
+use tokio::{
+    process::Command,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        Semaphore,
+    },
+};
+use tracing::{debug, info, warn};
+
+use crate::{events::Event, Config};
+
+// HookKind ///////////////////////////////////////////////////////////////////
+
+/// Which of a rule's hook commands to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookKind {
+    /// A link was created or retargeted, see `on_link`.
+    Link,
+    /// A link was deleted, see `on_unlink`.
+    Unlink,
+    /// A link or source couldn't get its name because it's taken, see `on_conflict`.
+    Conflict,
+}
+
+impl fmt::Display for HookKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookKind::Link => write!(f, "link"),
+            HookKind::Unlink => write!(f, "unlink"),
+            HookKind::Conflict => write!(f, "conflict"),
+        }
+    }
+}
+
+impl HookKind {
+    /// Returns which hook command runs for the event, if any.
+    pub fn of(event: &Event) -> Option<Self> {
+        match event {
+            Event::LinkCreated { .. } | Event::LinkRetargeted { .. } => Some(HookKind::Link),
+            Event::LinkRemoved { .. } => Some(HookKind::Unlink),
+            Event::Conflict { .. } => Some(HookKind::Conflict),
+            Event::ScanStarted | Event::ScanFinished { .. } | Event::Error { .. } => None,
+        }
+    }
+}
+
+// HookRunner /////////////////////////////////////////////////////////////////
+
+/// A hook command to run, as queued for the runner task.
+#[derive(Debug)]
+struct HookInvocation {
+    command: String,
+    kind: HookKind,
+    event: Event,
+}
+
+/// Queues hook commands for the runner task, so that the responder never waits on them.
+#[derive(Debug, Clone)]
+pub struct HookRunner {
+    tx: UnboundedSender<HookInvocation>,
+}
+
+impl HookRunner {
+    /// Queues the rule's hook command for the event, if it has one.
+    pub fn fire(&self, config: &Config, rule_idx: usize, event: &Event) {
+        let Some(kind) = HookKind::of(event) else {
+            return;
+        };
+        let rule = &config.rules[rule_idx];
+        let command = match kind {
+            HookKind::Link => &rule.on_link,
+            HookKind::Unlink => &rule.on_unlink,
+            HookKind::Conflict => &rule.on_conflict,
+        };
+        let Some(command) = command else {
+            return;
+        };
+
+        debug!("Queueing {} hook for rule {:?}", kind, rule.name);
+        let invocation = HookInvocation {
+            command: command.clone(),
+            kind,
+            event: event.clone(),
+        };
+        if self.tx.send(invocation).is_err() {
+            warn!("hook runner has stopped, not running hook");
+        }
+    }
+}
+
+/// Starts the task that runs hook commands, if any rule has one.
+///
+/// At most `max_concurrent_hooks` commands run at once; the rest wait in the queue, in order.
+/// Each is killed once it runs longer than `hook_timeout`.
+pub fn start_hook_runner(config: &Config) -> Option<HookRunner> {
+    if !config.rules.iter().any(|rule| rule.has_hooks()) {
+        return None;
+    }
+
+    let (tx, mut rx) = unbounded_channel::<HookInvocation>();
+    let semaphore = Arc::new(Semaphore::new(config.misc.max_concurrent_hooks.max(1)));
+    let timeout = config.misc.hook_timeout.0;
+    tokio::spawn(async move {
+        while let Some(invocation) = rx.recv().await {
+            let Ok(permit) = Arc::clone(&semaphore).acquire_owned().await else {
+                break;
+            };
+            tokio::spawn(async move {
+                run_hook(invocation, timeout).await;
+                drop(permit);
+            });
+        }
+    });
+
+    Some(HookRunner { tx })
+}
+
+/// Runs a hook command with `sh -c`, describing the event through environment variables, and
+/// logs how it exited.
+async fn run_hook(invocation: HookInvocation, timeout: Duration) {
+    let HookInvocation {
+        command,
+        kind,
+        event,
+    } = invocation;
+    let (rule, src_path, link_path, conflict_path) = match &event {
+        Event::LinkCreated {
+            rule,
+            src_path,
+            link_path,
+        }
+        | Event::LinkRemoved {
+            rule,
+            src_path,
+            link_path,
+        }
+        | Event::LinkRetargeted {
+            rule,
+            src_path,
+            link_path,
+            ..
+        } => (rule, src_path, Some(link_path), None),
+        Event::Conflict {
+            rule,
+            src_path,
+            conflict_path,
+            link_path,
+        } => (rule, src_path, link_path.as_ref(), Some(conflict_path)),
+        Event::ScanStarted | Event::ScanFinished { .. } | Event::Error { .. } => return,
+    };
+
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(&command)
+        .env("FILETAGS_EVENT", kind.to_string())
+        .env("FILETAGS_RULE", rule)
+        .env("FILETAGS_SOURCE", src_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // in a group of its own, so that what the shell started can be killed along with it
+        .process_group(0)
+        .kill_on_drop(true);
+    if let Some(link_path) = link_path {
+        cmd.env("FILETAGS_LINK", link_path);
+    }
+    if let Some(conflict_path) = conflict_path {
+        cmd.env("FILETAGS_CONFLICT", conflict_path);
+    }
+
+    debug!("Running {} hook for rule {:?}: {}", kind, rule, command);
+    let child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            warn!(
+                "failed to run {} hook for rule {:?}: {}: {}",
+                kind, rule, command, e
+            );
+            return;
+        }
+    };
+    let pid = child.id();
+    match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Err(_) => {
+            if let Some(pid) = pid {
+                kill_process_group(pid);
+            }
+            warn!(
+                "{} hook for rule {:?} timed out after {:?}, killed it: {}",
+                kind, rule, timeout, command
+            )
+        }
+        Ok(Err(e)) => warn!(
+            "failed to run {} hook for rule {:?}: {}: {}",
+            kind, rule, command, e
+        ),
+        Ok(Ok(output)) if output.status.success() => info!(
+            "{} hook for rule {:?} exited with {} for {:?}",
+            kind, rule, output.status, src_path
+        ),
+        Ok(Ok(output)) => warn!(
+            "{} hook for rule {:?} exited with {} for {:?}: {}",
+            kind,
+            rule,
+            output.status,
+            src_path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    }
+}
+
+/// Kills every process in the group led by `pgid`, i.e. a hook's shell and what it started.
+fn kill_process_group(pgid: u32) {
+    // SAFETY: kill only sends a signal, a negative pid addresses the process group
+    if unsafe { libc::kill(-(pgid as libc::pid_t), libc::SIGKILL) } != 0 {
+        debug!(
+            "Failed to kill process group {}: {}",
+            pgid,
+            std::io::Error::last_os_error()
+        );
+    }
+}
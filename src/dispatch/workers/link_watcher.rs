@@ -0,0 +1,163 @@
+use std::{future::Future, path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use notify::{
+    event::{ModifyKind, RenameMode},
+    Event, EventKind, RecursiveMode, Watcher,
+};
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::{link_dir_indices, Config, Message, Sender};
+
+/// How long to wait for the other half of a rename before treating a path that was moved away
+/// as removed from the link_dir.
+const RENAME_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Used in `Message::LinkDirEvent(LinkDirEvent)`.
+/// A change made to a link_dir by something other than filetags.
+#[derive(Clone, Debug)]
+pub struct LinkDirEvent {
+    pub rule_idx: usize,
+    pub link_idx: usize,
+    pub kind: LinkDirEventKind,
+}
+
+#[derive(Clone, Debug)]
+pub enum LinkDirEventKind {
+    /// A path was deleted, or moved out of the link_dir (e.g. to the trash).
+    Removed(PathBuf),
+    /// A path was renamed within the link_dir.
+    Renamed { from: PathBuf, to: PathBuf },
+    /// A path was created, or moved into the link_dir from elsewhere, in which case `tracker`
+    /// is the Notify rename tracker of the move.
+    Created {
+        path: PathBuf,
+        tracker: Option<usize>,
+    },
+}
+
+/// Create and start the INotifyWatchers for the link_dirs of rules that need them.
+pub fn start_link_watchers(
+    tx: &Sender<Message>,
+    config: &Arc<Config>,
+) -> anyhow::Result<Vec<JoinHandle<anyhow::Result<()>>>> {
+    Ok(create_link_watcher_closures(tx, config)?
+        .into_iter()
+        .map(|future| tokio::spawn(future))
+        .collect::<Vec<_>>())
+}
+
+/// Create and return a Vec of closures of link_dir watchers.
+///
+/// Unlike the watch_dir watchers, the raw Notify events are first collected by the closure,
+/// so that the two halves of a rename can be paired up before anything is sent.
+fn create_link_watcher_closures(
+    tx: &Sender<Message>,
+    config: &Arc<Config>,
+) -> anyhow::Result<Vec<impl Future<Output = anyhow::Result<()>>>> {
+    link_dir_indices(config)
+        .filter(|(rule_idx, _)| config.rules[*rule_idx].settings.watches_link_dirs())
+        .map(|(rule_idx, link_idx)| -> anyhow::Result<_> {
+            let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel::<Event>();
+            let mut watcher = notify::recommended_watcher(move |res: Result<Event, _>| match res {
+                Ok(event) => {
+                    if let Err(e) = event_tx.send(event) {
+                        debug!("LINK WATCHER FAILED TO SEND EVENT: {:?}", e);
+                    }
+                }
+                Err(e) => debug!("LINK WATCH ERROR! {}", e),
+            })
+            .context("creating notify watcher")?;
+            // start the watcher at the path now, so that it's watching once launched
+            let path = &config.rules[rule_idx].link_dirs[link_idx];
+            watcher
+                .watch(path, RecursiveMode::Recursive)
+                .with_context(|| format!("watching link_dir {:?}", path))?;
+            let tx = tx.clone();
+            let send = move |kind: LinkDirEventKind| {
+                tx.send(Message::LinkDirEvent(LinkDirEvent {
+                    rule_idx,
+                    link_idx,
+                    kind,
+                }))
+                .context("sending message LinkDirEvent")
+            };
+
+            Ok(async move {
+                // keep it alive
+                let _watcher = watcher;
+
+                // a `From` event still waiting for its `Both`
+                let mut pending_from: Option<Event> = None;
+                loop {
+                    let event = if pending_from.is_some() {
+                        match tokio::time::timeout(RENAME_TIMEOUT, event_rx.recv()).await {
+                            Ok(event) => event,
+                            Err(_) => {
+                                // never paired up, so it was moved out of the link_dir
+                                for path in pending_from.take().into_iter().flat_map(|e| e.paths) {
+                                    send(LinkDirEventKind::Removed(path))?;
+                                }
+                                continue;
+                            }
+                        }
+                    } else {
+                        event_rx.recv().await
+                    };
+                    let event = event.context("link watcher waiting for Event")?;
+
+                    match event.kind {
+                        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                            if let Some(unpaired) = pending_from.replace(event) {
+                                for path in unpaired.paths {
+                                    send(LinkDirEventKind::Removed(path))?;
+                                }
+                            }
+                        }
+                        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                            // renamed within the link_dir
+                            pending_from = None;
+                            if let [from, to] = &event.paths[..] {
+                                send(LinkDirEventKind::Renamed {
+                                    from: from.clone(),
+                                    to: to.clone(),
+                                })?;
+                            }
+                        }
+                        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                            let paired = pending_from
+                                .as_ref()
+                                .is_some_and(|from| from.tracker() == event.tracker());
+                            if !paired {
+                                // moved in from elsewhere. give the watch_dir watchers a
+                                // moment to report where it came from first
+                                tokio::time::sleep(RENAME_TIMEOUT).await;
+                                for path in event.paths.iter().cloned() {
+                                    send(LinkDirEventKind::Created {
+                                        path,
+                                        tracker: event.tracker(),
+                                    })?;
+                                }
+                            }
+                        }
+                        EventKind::Create(_) => {
+                            for path in event.paths {
+                                send(LinkDirEventKind::Created {
+                                    path,
+                                    tracker: None,
+                                })?;
+                            }
+                        }
+                        EventKind::Remove(_) => {
+                            for path in event.paths {
+                                send(LinkDirEventKind::Removed(path))?;
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+}
@@ -35,6 +35,10 @@ fn create_cleaner_closures(
                             tx.send(Message::Action(Action::CleanDir(rule_idx, link_idx)))
                                 .context("sending message CleanDir")?;
                         }
+                        if !rule.playlists.is_empty() {
+                            tx.send(Message::Action(Action::CleanPlaylists(rule_idx)))
+                                .context("sending message CleanPlaylists")?;
+                        }
                     }
                 })
             } else {
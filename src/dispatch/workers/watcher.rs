@@ -1,14 +1,17 @@
-use std::{future::Future, sync::Arc, time::Duration};
+use std::{future::Future, path::PathBuf, sync::Arc, time::Duration};
 
 use anyhow::Context;
 use notify::{
-    event::{ModifyKind, RenameMode},
+    event::{AccessKind, AccessMode, MetadataKind, ModifyKind, RenameMode},
     Event, EventKind, INotifyWatcher, RecursiveMode, Watcher,
 };
 use tokio::task::JoinHandle;
 use tracing::debug;
 
-use crate::{match_event_kinds, watch_dir_indices, Config, Sender};
+use crate::{
+    is_sidecar, match_event_kinds, match_metadata_event_kinds, match_removal_event_kinds,
+    match_write_event_kinds, watch_dir_indices, Config, Sender,
+};
 
 use crate::Message;
 
@@ -33,35 +36,88 @@ pub fn start_watchers(
 }
 
 /// Create and return a Vec of closures of Watchers.
+///
+/// Query rules don't get watchers of their own, since they're updated through the events of
+/// the rules they reference. Nor do watch_dirs matched by globs, whose watchers come and go
+/// with them (see `WorkerType::GlobWatchers`).
 fn create_watcher_closures(
     tx: &Sender<Message>,
     config: &Arc<Config>,
 ) -> anyhow::Result<Vec<impl Future<Output = anyhow::Result<()>>>> {
     watch_dir_indices(config)
-        .map(|(rule_idx, watch_idx)| -> anyhow::Result<_> {
-            let mut watcher = create_watcher(tx.clone(), rule_idx, watch_idx)?;
+        .filter(|(rule_idx, watch_idx)| {
+            let rule = &config.rules[*rule_idx];
+            rule.query.is_none() && !rule.watch_dir_is_globbed(*watch_idx)
+        })
+        .map(|(rule_idx, watch_idx)| {
             let path = config.rules[rule_idx].watch_dirs[watch_idx].clone();
-            Ok(async move {
-                // start the watcher at the path
-                watcher.watch(path.as_path(), RecursiveMode::Recursive)?;
-                // keep it alive
-                loop {
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                }
-            })
+            create_watcher_closure(tx, config, rule_idx, watch_idx, path)
         })
         .collect::<anyhow::Result<Vec<_>>>()
 }
 
+/// Create the closure of the Watcher of a single watch_dir, which may not be in the config's
+/// watch_dirs yet.
+pub fn create_watcher_closure(
+    tx: &Sender<Message>,
+    config: &Config,
+    rule_idx: usize,
+    watch_idx: usize,
+    path: PathBuf,
+) -> anyhow::Result<impl Future<Output = anyhow::Result<()>>> {
+    let rule = &config.rules[rule_idx];
+    let watch_metadata = rule.xattr_tag.is_some();
+    let watch_sidecars = rule.sidecar_tag.is_some();
+    let watch_writes = rule.settings.remember_tags;
+    let mut watcher = create_watcher(
+        tx.clone(),
+        rule_idx,
+        watch_idx,
+        watch_metadata,
+        watch_sidecars,
+        watch_writes,
+    )?;
+    // start the watcher at the path now, so that it's watching once launched
+    watcher
+        .watch(&path, RecursiveMode::Recursive)
+        .with_context(|| format!("watching watch_dir {:?}", path))?;
+    Ok(async move {
+        // keep it alive
+        let _watcher = watcher;
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    })
+}
+
 /// Create and return an INotifyWatcher. Don't start them just yet.
+///
+/// Metadata events are only forwarded if `watch_metadata` is set, since they're frequent and
+/// only relevant to rules that match on xattrs. Likewise, write events are only forwarded for
+/// sidecar files if `watch_sidecars` is set, and closed writes to any file if `watch_writes` is
+/// set, for rules that recall tags from file contents.
 fn create_watcher(
     tx: Sender<Message>,
     rule_idx: usize,
     watch_idx: usize,
+    watch_metadata: bool,
+    watch_sidecars: bool,
+    watch_writes: bool,
 ) -> anyhow::Result<INotifyWatcher> {
     notify::recommended_watcher(move |res: Result<Event, _>| match res {
         Ok(event) => {
-            if let match_event_kinds!() = event.kind {
+            let forward = match event.kind {
+                match_event_kinds!() | match_removal_event_kinds!() => true,
+                match_metadata_event_kinds!() => watch_metadata,
+                match_write_event_kinds!() => {
+                    (watch_sidecars && event.paths.iter().any(|p| is_sidecar(p)))
+                        || (watch_writes
+                            && event.kind
+                                == EventKind::Access(AccessKind::Close(AccessMode::Write)))
+                }
+                _ => false,
+            };
+            if forward {
                 match tx.send(Message::NotifyEvent(NotifyEvent {
                     rule_idx,
                     watch_idx,
@@ -0,0 +1,67 @@
+use std::{io, path::PathBuf};
+
+// Error //////////////////////////////////////////////////////////////////////
+
+/// The errors returned by the public API, so that callers embedding filetags can match on them.
+///
+/// Internally, errors are `anyhow::Error`s with context added as they bubble up. They're turned
+/// into an `Error` at the API boundary, by looking for a typed error in their chain (see the
+/// `From<anyhow::Error>` impl).
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The config is invalid, e.g. it doesn't parse, or two rules share a name.
+    #[error("invalid config: {0}")]
+    Config(String),
+
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("invalid regex: {0}")]
+    Regex(#[from] regex::Error),
+
+    /// A path filetags needs is taken by something it won't replace, e.g. a regular file where
+    /// a link should go.
+    #[error("conflict: {0:?} already exists")]
+    Conflict(PathBuf),
+
+    /// A watch couldn't be set up.
+    #[error("watcher: {0}")]
+    Watcher(#[from] notify::Error),
+
+    /// Anything else, with its full chain of context.
+    #[error("{0:#}")]
+    Other(anyhow::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        let context = e.to_string();
+        let e = match e.downcast::<Error>() {
+            Ok(error) => return error,
+            Err(e) => e,
+        };
+        let e = match e.downcast::<regex::Error>() {
+            Ok(source) => return Error::Regex(source),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<notify::Error>() {
+            Ok(source) => return Error::Watcher(source),
+            Err(e) => e,
+        };
+        match e.downcast::<io::Error>() {
+            // without any context, the message would be the source's own
+            Ok(source) if context == source.to_string() => Error::Io {
+                context: "I/O error".to_string(),
+                source,
+            },
+            Ok(source) => Error::Io { context, source },
+            Err(e) => Error::Other(e),
+        }
+    }
+}
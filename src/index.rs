@@ -0,0 +1,345 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    env, fmt,
+    fs::{self, File},
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::write_file_atomically;
+
+// ContentKey /////////////////////////////////////////////////////////////////
+
+/// Identifies a file by its content, through its size and a fast hash of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ContentKey {
+    pub size: u64,
+    pub hash: u64,
+}
+
+impl ContentKey {
+    /// Hashes the content of the file at the path.
+    pub fn of_file(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| format!("opening file: {:?}", path))?;
+        let mut reader = BufReader::new(file);
+        let mut hasher = Xxh3::new();
+        let mut buf = [0; 64 * 1024];
+        let mut size = 0;
+        loop {
+            let read = reader
+                .read(&mut buf)
+                .with_context(|| format!("reading file: {:?}", path))?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+            size += read as u64;
+        }
+
+        Ok(Self {
+            size,
+            hash: hasher.digest(),
+        })
+    }
+}
+
+impl fmt::Display for ContentKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}-{}", self.hash, self.size)
+    }
+}
+
+// HashCache //////////////////////////////////////////////////////////////////
+
+/// Caches the content keys of files, so that unchanged files aren't hashed again.
+/// A cached key is only used while the file's size and mtime are unchanged.
+#[derive(Debug, Default)]
+pub struct HashCache {
+    entries: HashMap<PathBuf, (SystemTime, ContentKey)>,
+    by_key: HashMap<ContentKey, BTreeSet<PathBuf>>,
+}
+
+impl HashCache {
+    /// Returns the content key of the file at the path, hashing it if it changed.
+    pub fn get(&mut self, path: &Path) -> anyhow::Result<ContentKey> {
+        let metadata = fs::metadata(path)
+            .with_context(|| format!("performing metadata call on path: {:?}", path))?;
+        let mtime = metadata
+            .modified()
+            .with_context(|| format!("getting mtime of path: {:?}", path))?;
+        if let Some((cached_mtime, key)) = self.entries.get(path) {
+            if *cached_mtime == mtime && key.size == metadata.len() {
+                return Ok(*key);
+            }
+        }
+
+        let key = ContentKey::of_file(path)?;
+        if let Some((_, old_key)) = self.entries.insert(path.to_path_buf(), (mtime, key)) {
+            if let Some(paths) = self.by_key.get_mut(&old_key) {
+                paths.remove(path);
+            }
+        }
+        self.by_key
+            .entry(key)
+            .or_default()
+            .insert(path.to_path_buf());
+
+        Ok(key)
+    }
+
+    /// Returns the paths that were last hashed to the content key.
+    /// Their content may have changed since, so check them with `get`.
+    pub fn cached_paths(&self, key: &ContentKey) -> Vec<PathBuf> {
+        self.by_key
+            .get(key)
+            .map(|paths| paths.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+// TagIndex ///////////////////////////////////////////////////////////////////
+
+/// Everything remembered about a piece of content.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    #[serde(flatten)]
+    pub key: ContentKey,
+    /// The names of the rules it was tagged for.
+    pub tags: BTreeSet<String>,
+    /// Where it was seen tagged. Paths may no longer exist, see `prune_missing`.
+    pub paths: BTreeSet<PathBuf>,
+    /// When a tag or path was last added, in seconds since the epoch.
+    pub last_seen: u64,
+    /// The paths linked without being tagged, by rule name, see `RecallPolicy::Link`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub linked: BTreeMap<String, BTreeSet<PathBuf>>,
+}
+
+/// The on-disk layout of the index.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IndexFile {
+    entries: Vec<IndexEntry>,
+}
+
+/// A persistent index of the content of tagged files, so that tags can be recalled when the
+/// same content appears again untagged, e.g. after re-downloading or restoring it.
+#[derive(Debug, Default)]
+pub struct TagIndex {
+    path: PathBuf,
+    entries: HashMap<ContentKey, IndexEntry>,
+    by_path: HashMap<PathBuf, ContentKey>,
+}
+
+impl TagIndex {
+    /// Loads the index from the path. A missing file is an empty index.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut index = Self {
+            path: path.to_path_buf(),
+            ..Self::default()
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(index),
+            Err(e) => return Err(e).with_context(|| format!("reading index: {:?}", path)),
+        };
+        let file: IndexFile =
+            serde_yml::from_str(&contents).with_context(|| format!("parsing index: {:?}", path))?;
+        for entry in file.entries {
+            index.insert_entry(entry);
+        }
+
+        Ok(index)
+    }
+
+    /// Writes the index back to where it was loaded from.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let mut entries = self.entries.values().cloned().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.key);
+        let contents = serde_yml::to_string(&IndexFile { entries })?;
+
+        write_file_atomically(&self.path, contents).context("writing index")
+    }
+
+    /// Returns every entry, ordered by content.
+    pub fn entries(&self) -> Vec<&IndexEntry> {
+        let mut entries = self.entries.values().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.key);
+        entries
+    }
+
+    /// Returns the entry for the content, if it's remembered.
+    pub fn get(&self, key: &ContentKey) -> Option<&IndexEntry> {
+        self.entries.get(key)
+    }
+
+    /// Returns the entry for the content last seen at the path, if any.
+    pub fn get_by_path(&self, path: &Path) -> Option<&IndexEntry> {
+        self.by_path.get(path).and_then(|key| self.entries.get(key))
+    }
+
+    /// Returns whether the file at the path is remembered as tagged for the rule, without
+    /// hashing it. Only its size is compared, so this is a quick check for known paths.
+    pub fn recalls(&self, path: &Path, tag: &str) -> bool {
+        self.get_by_path(path).is_some_and(|entry| {
+            entry.tags.contains(tag)
+                && fs::metadata(path).is_ok_and(|metadata| metadata.len() == entry.key.size)
+        })
+    }
+
+    /// Remembers that the content at the path is tagged for the rule.
+    /// Returns whether anything changed, i.e. whether the index needs saving.
+    pub fn remember(&mut self, key: ContentKey, path: &Path, tag: &str) -> bool {
+        // the path may have held other content before
+        if let Some(old_key) = self.by_path.get(path).copied() {
+            if old_key != key {
+                self.remove_path(old_key, path);
+            }
+        }
+
+        let entry = self.entries.entry(key).or_insert_with(|| IndexEntry {
+            key,
+            tags: BTreeSet::new(),
+            paths: BTreeSet::new(),
+            last_seen: 0,
+            linked: BTreeMap::new(),
+        });
+        let changed = entry.tags.insert(tag.to_string()) | entry.paths.insert(path.to_path_buf());
+        if changed {
+            entry.last_seen = unix_now();
+        }
+        self.by_path.insert(path.to_path_buf(), key);
+
+        changed
+    }
+
+    /// Remembers that the content at the path was linked for the rule without being tagged,
+    /// see `RecallPolicy::Link`. Returns whether anything changed.
+    pub fn remember_link(&mut self, key: ContentKey, path: &Path, tag: &str) -> bool {
+        let changed = self.remember(key, path, tag);
+        let Some(entry) = self.entries.get_mut(&key) else {
+            return changed;
+        };
+
+        changed
+            | entry
+                .linked
+                .entry(tag.to_string())
+                .or_default()
+                .insert(path.to_path_buf())
+    }
+
+    /// Returns whether the file at the path was linked for the rule without being tagged, see
+    /// `remember_link`. Like `recalls`, only its size is compared.
+    pub fn recalls_link(&self, path: &Path, tag: &str) -> bool {
+        self.get_by_path(path).is_some_and(|entry| {
+            entry
+                .linked
+                .get(tag)
+                .is_some_and(|paths| paths.contains(path))
+                && fs::metadata(path).is_ok_and(|metadata| metadata.len() == entry.key.size)
+        })
+    }
+
+    /// Forgets that the content is tagged for the rule, dropping the entry once it has no tags
+    /// left. Returns whether anything changed.
+    pub fn forget_tag(&mut self, key: ContentKey, tag: &str) -> bool {
+        let Some(entry) = self.entries.get_mut(&key) else {
+            return false;
+        };
+        entry.linked.remove(tag);
+        let changed = entry.tags.remove(tag);
+        if entry.tags.is_empty() {
+            self.forget(key);
+        }
+
+        changed
+    }
+
+    /// Forgets the content entirely. Returns whether it was remembered.
+    pub fn forget(&mut self, key: ContentKey) -> bool {
+        let Some(entry) = self.entries.remove(&key) else {
+            return false;
+        };
+        for path in entry.paths {
+            self.by_path.remove(&path);
+        }
+
+        true
+    }
+
+    /// Forgets paths that no longer exist, but keeps the content itself, so that its tags can
+    /// still be recalled. Returns the number of paths forgotten.
+    pub fn prune_missing(&mut self) -> usize {
+        let missing = self
+            .by_path
+            .iter()
+            .filter(|(path, _)| !path.exists())
+            .map(|(path, key)| (path.clone(), *key))
+            .collect::<Vec<_>>();
+        for (path, key) in &missing {
+            self.remove_path(*key, path);
+        }
+
+        missing.len()
+    }
+
+    /// Forgets content last seen longer ago than `age`. Returns the number of entries forgotten.
+    pub fn prune_older_than(&mut self, age: Duration) -> usize {
+        let cutoff = unix_now().saturating_sub(age.as_secs());
+        let old = self
+            .entries
+            .values()
+            .filter(|entry| entry.last_seen < cutoff)
+            .map(|entry| entry.key)
+            .collect::<Vec<_>>();
+        for key in &old {
+            self.forget(*key);
+        }
+
+        old.len()
+    }
+
+    fn insert_entry(&mut self, entry: IndexEntry) {
+        for path in &entry.paths {
+            self.by_path.insert(path.clone(), entry.key);
+        }
+        self.entries.insert(entry.key, entry);
+    }
+
+    fn remove_path(&mut self, key: ContentKey, path: &Path) {
+        self.by_path.remove(path);
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.paths.remove(path);
+            for paths in entry.linked.values_mut() {
+                paths.remove(path);
+            }
+            entry.linked.retain(|_, paths| !paths.is_empty());
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The default location of the index, under `$XDG_DATA_HOME`.
+pub fn default_index_path() -> PathBuf {
+    let data_dir = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            env::var_os("HOME")
+                .map(PathBuf::from)
+                .map(|p| p.join(".local").join("share"))
+                .expect("Cannot find HOME directory")
+        });
+
+    data_dir.join("filetags").join("index.yml")
+}
@@ -6,16 +6,26 @@ use tracing::debug;
 
 // modules
 mod args;
+mod commands;
 mod config;
 mod dispatch;
+mod error;
+mod index;
 mod logger;
+mod matching;
+mod membership;
 mod utils;
 
 // re-export
 pub use args::*;
+pub use commands::*;
 pub use config::*;
 pub use dispatch::*;
+pub use error::*;
+pub use index::*;
 pub use logger::*;
+pub use matching::*;
+pub use membership::*;
 pub use utils::*;
 
 use crate::{actions::Action, workers::WorkerType};
@@ -24,18 +34,22 @@ use crate::{actions::Action, workers::WorkerType};
 // - prevent recursive searching when LinkDir is within WatchDir or symlinking dirs.
 
 /// The default run command.
-pub async fn run() -> anyhow::Result<()> {
+pub async fn run() -> Result<()> {
     let args = Args::parse();
+    if let Some(Command::Config(config_command)) = &args.command {
+        return Ok(run_config_command(config_command, args.config_format)?);
+    }
+    if let Some(command) = &args.command {
+        let config: Arc<Config> = Config::create(&args)?;
+        let _logger = Logger::new();
+        return Ok(run_command(&config, command).await?);
+    }
     let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
     run_with_args(args, tx, rx).await
 }
 
 /// Run the program with args, tx, and rx.
-pub async fn run_with_args(
-    args: Args,
-    tx: Sender<Message>,
-    rx: Receiver<Message>,
-) -> anyhow::Result<()> {
+pub async fn run_with_args(args: Args, tx: Sender<Message>, rx: Receiver<Message>) -> Result<()> {
     // create a Config from Args
     let config: Arc<Config> = Config::create(&args)?;
     let _logger = Logger::new();
@@ -49,35 +63,61 @@ pub async fn run_with_config<F>(
     tx: Sender<Message>,
     rx: Receiver<Message>,
     test_hook: Option<F>,
-) -> anyhow::Result<()>
+) -> Result<()>
 where
     F: Fn() + Send + 'static,
 {
     span_enter!(DEBUG, "running");
     debug!("Config: {:#?}", config);
+    config.check_matchers()?;
 
     // start responder
-    let dispatcher = Dispatcher::new(rx, tx, Arc::clone(&config))?
-        // create all necessary dirs
-        .run(Action::MakeNecessaryDirs)?
-        // clean all broken or innapropriate links in link_dirs
-        .run(Action::CleanAll)?
-        // maybe create symlinks as appropriate
-        .run(Action::SymlinkAll)?
+    let dispatcher = Dispatcher::new(rx, tx, Arc::clone(&config))?;
+    // create all necessary dirs, clean all broken or innapropriate links in link_dirs, then
+    // maybe create symlinks as appropriate, waiting for each so that workers start afterwards
+    for action in [
+        Action::MakeNecessaryDirs,
+        Action::CleanAll,
+        Action::SymlinkAll,
+    ] {
+        let report = dispatcher.perform(action).await?;
+        if let Err(e) = report.into_result() {
+            send_shutdown(&dispatcher.tx);
+            return Err(e);
+        }
+    }
+    let dispatcher = dispatcher
         // start all link cleaners
         .launch(WorkerType::Cleaners)?
         // setup all watchers
-        .launch(WorkerType::Watchers)?;
+        .launch(WorkerType::Watchers)?
+        // follow the directories matched by watch_dir globs as they come and go
+        .launch(WorkerType::GlobWatchers)?
+        // setup link_dir watchers, for rules that react to changes made in their link_dirs
+        .launch(WorkerType::LinkWatchers)?
+        // serve events to `filetags events`
+        .launch(WorkerType::EventServer)?;
 
-    // maybe run test hook (for integration tests)
-    test_hook.inspect(|hook_fn| {
-        span_enter!(DEBUG, "test_hook");
-        hook_fn();
-    });
+    // maybe run test hook (for integration tests), off the runtime's threads so that it may
+    // block on reports (see `send_request`)
+    if let Some(hook_fn) = test_hook {
+        let running = tracing::Span::current();
+        let hook = tokio::task::spawn_blocking(move || {
+            let _running = running.enter();
+            span_enter!(DEBUG, "test_hook");
+            hook_fn();
+        });
+        if let Err(e) = hook.await {
+            std::panic::resume_unwind(e.into_panic());
+        }
+    }
 
     // if running as a systemd service, notify systemd that the service is ready
     if config.misc.systemd_service {
-        daemon::notify(false, [(daemon::STATE_READY, "1")].iter())?;
+        daemon::notify(false, [(daemon::STATE_READY, "1")].iter()).map_err(|source| Error::Io {
+            context: "notifying systemd".to_string(),
+            source,
+        })?;
     }
 
     // block this thread until the responder thread completes
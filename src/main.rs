@@ -2,5 +2,5 @@ use filetags::run;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    run().await
+    Ok(run().await?)
 }
@@ -0,0 +1,280 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use tracing::warn;
+
+use crate::{
+    is_sidecar, path_is_under_any_dirs, read_sidecar_tags, read_xattr_tags, Config, Rule,
+    ScriptVerdict,
+};
+
+mod matcher;
+
+pub use matcher::{Match, MatchInput, Matcher, Matchers};
+
+// Rule membership ////////////////////////////////////////////////////////////
+
+/// Returns whether the path is a member of the rule at `rule_idx`, see `rule_verdict`.
+pub fn rule_matches(config: &Config, rule_idx: usize, path: &Path) -> anyhow::Result<bool> {
+    Ok(rule_verdict(config, rule_idx, path)? != ScriptVerdict::Reject)
+}
+
+/// Returns Reject if the path isn't a member of the rule at `rule_idx`, and otherwise what the
+/// rule's `script` decides about it, so that callers needing both only run the script once.
+///
+/// A path is a member if it's tagged itself (see `rule_matches_directly`), or, if the rule has
+/// `inherit_from_parents` set, if any of its ancestor directories below the watch_dir are.
+pub fn rule_verdict(
+    config: &Config,
+    rule_idx: usize,
+    path: &Path,
+) -> anyhow::Result<ScriptVerdict> {
+    let rule = &config.rules[rule_idx];
+
+    let tagged = rule_matches_directly(config, rule_idx, path)?
+        || (rule.settings.inherit_from_parents
+            && tagged_ancestor_dir(config, rule_idx, path)?.is_some());
+
+    // predicates are only checked once tagged, since they may need to read the file
+    if !tagged || !rule_predicates_match(rule, path)? {
+        return Ok(ScriptVerdict::Reject);
+    }
+    rule_script_verdict(config, rule_idx, path)
+}
+
+/// Returns what the rule's `script` decides about the path, or Accept if it has none.
+///
+/// A script that fails at runtime is logged, and rejects the path.
+pub fn rule_script_verdict(
+    config: &Config,
+    rule_idx: usize,
+    path: &Path,
+) -> anyhow::Result<ScriptVerdict> {
+    let rule = &config.rules[rule_idx];
+    let Some(script) = &rule.script else {
+        return Ok(ScriptVerdict::Accept);
+    };
+    let Some(watch_idx) = watch_idx_for_path(config, rule_idx, path) else {
+        return Ok(ScriptVerdict::Reject);
+    };
+
+    let input = MatchInput::new(path, &rule.watch_dirs[watch_idx], rule.settings.match_on);
+    let captures = rule_tag_match(config, rule, &input)?
+        .unwrap_or_default()
+        .captures;
+    match script.eval(&input, &captures) {
+        Ok(verdict) => Ok(verdict),
+        Err(e) => {
+            warn!(
+                "script of rule {:?} failed for {:?}: {:#}",
+                rule.name, path, e
+            );
+            Ok(ScriptVerdict::Reject)
+        }
+    }
+}
+
+/// Returns whether the path passes the rule's file-attribute predicates, if any.
+///
+/// The size, extension and MIME predicates only apply to files, so directories always pass
+/// them. The age predicates apply to both.
+pub fn rule_predicates_match(rule: &Rule, path: &Path) -> anyhow::Result<bool> {
+    if !rule.has_predicates() {
+        return Ok(true);
+    }
+    let metadata = fs::metadata(path)
+        .with_context(|| format!("performing metadata call on path: {:?}", path))?;
+
+    if rule.newer_than.is_some() || rule.older_than.is_some() {
+        let modified = metadata
+            .modified()
+            .with_context(|| format!("getting mtime of path: {:?}", path))?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if rule.newer_than.is_some_and(|newer_than| age > newer_than.0)
+            || rule.older_than.is_some_and(|older_than| age < older_than.0)
+        {
+            return Ok(false);
+        }
+    }
+
+    if !metadata.is_file() {
+        return Ok(true);
+    }
+
+    if rule.min_size.is_some_and(|min| metadata.len() < min.0)
+        || rule.max_size.is_some_and(|max| metadata.len() > max.0)
+    {
+        return Ok(false);
+    }
+
+    if !rule.extensions.is_empty() {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        if !rule
+            .extensions
+            .iter()
+            .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(extension))
+        {
+            return Ok(false);
+        }
+    }
+
+    if let Some(mime) = &rule.mime {
+        let detected = infer::get_from_path(path)
+            .with_context(|| format!("detecting MIME type of path: {:?}", path))?;
+        if !detected.is_some_and(|t| mime.matches(t.mime_type())) {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Returns whether the path itself is tagged for the rule at `rule_idx`.
+///
+/// For a regex rule, the path must be under one of its watch_dirs, and be matched by its regexes
+/// or any of its registered `matchers` (see `Matcher`), or carry its `xattr_tag` or have its
+/// `sidecar_tag` declared in a sidecar file.
+/// For a query rule, the query is evaluated against the membership of the rules it references,
+/// where a path is only a member of a referenced rule if it's also under its watch_dirs.
+pub fn rule_matches_directly(
+    config: &Config,
+    rule_idx: usize,
+    path: &Path,
+) -> anyhow::Result<bool> {
+    let rule = &config.rules[rule_idx];
+
+    match &rule.query {
+        Some(query) => query.eval(&mut |name| {
+            let idx = config
+                .rule_idx(name)
+                .with_context(|| format!("looking up rule referenced in query: {:?}", name))?;
+            Ok(path_is_under_any_dirs(path, &config.rules[idx].watch_dirs)?
+                && rule_matches(config, idx, path)?)
+        }),
+        None => {
+            let Some(watch_idx) = watch_idx_for_path(config, rule_idx, path) else {
+                return Ok(false);
+            };
+            // the script is checked along with the predicates, see `rule_matches`
+            if rule.script.is_some() && !rule.has_tag_sources() {
+                return Ok(true);
+            }
+            let input = MatchInput::new(path, &rule.watch_dirs[watch_idx], rule.settings.match_on);
+            if rule_tag_match(config, rule, &input)?.is_some() {
+                return Ok(true);
+            }
+            if let Some(tag) = &rule.xattr_tag {
+                if read_xattr_tags(path, &rule.settings.xattr_name)?.contains(tag) {
+                    return Ok(true);
+                }
+            }
+            match &rule.sidecar_tag {
+                Some(tag) => Ok(read_sidecar_tags(path)?.contains(tag)),
+                None => Ok(false),
+            }
+        }
+    }
+}
+
+/// Returns the match of the rule's `regex`, or else of the first of its `matchers` that
+/// matches, so that the script sees what the winning one captured.
+fn rule_tag_match(
+    config: &Config,
+    rule: &Rule,
+    input: &MatchInput,
+) -> anyhow::Result<Option<Match>> {
+    if let Some(found) = rule.regex.matches(input)? {
+        return Ok(Some(found));
+    }
+    for name in &rule.matchers {
+        let matcher = config
+            .matchers
+            .get(name)
+            .with_context(|| format!("no matcher is registered as {:?}", name))?;
+        if let Some(found) = matcher
+            .matches(input)
+            .with_context(|| format!("running matcher {:?}", name))?
+        {
+            return Ok(Some(found));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns whether the rule should link the path itself, given its `targets` setting.
+///
+/// When the rule links directories, paths inside a tagged directory are skipped unless
+/// `link_inside_tagged_dirs` is set, since the directory is already linked as a unit.
+/// Sidecar files are skipped for rules that read them.
+pub fn rule_accepts_target(config: &Config, rule_idx: usize, path: &Path) -> anyhow::Result<bool> {
+    let rule = &config.rules[rule_idx];
+    let settings = &rule.settings;
+    // sidecar files describe tags, they're never linked themselves
+    if rule.sidecar_tag.is_some() && is_sidecar(path) {
+        return Ok(false);
+    }
+    let metadata = fs::metadata(path)
+        .with_context(|| format!("performing metadata call on path: {:?}", path))?;
+
+    let accepted = (metadata.is_file() && settings.targets.includes_files())
+        || (metadata.is_dir() && settings.targets.includes_dirs());
+    if accepted && settings.targets.includes_dirs() && !settings.link_inside_tagged_dirs {
+        return Ok(tagged_ancestor_dir(config, rule_idx, path)?.is_none());
+    }
+
+    Ok(accepted)
+}
+
+/// Returns the closest ancestor directory of the path that's tagged for the rule.
+/// Only directories below the watch_dir are considered, not the watch_dir itself.
+pub fn tagged_ancestor_dir(
+    config: &Config,
+    rule_idx: usize,
+    path: &Path,
+) -> anyhow::Result<Option<PathBuf>> {
+    let Some(watch_idx) = watch_idx_for_path(config, rule_idx, path) else {
+        return Ok(None);
+    };
+    let watch_dir = &config.rules[rule_idx].watch_dirs[watch_idx];
+
+    for ancestor in path.ancestors().skip(1) {
+        if ancestor == watch_dir || !ancestor.starts_with(watch_dir) {
+            break;
+        }
+        if rule_matches_directly(config, rule_idx, ancestor)? {
+            return Ok(Some(ancestor.to_path_buf()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns whether the rule at `rule_idx` is a query rule that references `input_idx`,
+/// either directly or through other query rules.
+pub fn query_depends_on(config: &Config, rule_idx: usize, input_idx: usize) -> bool {
+    let Some(query) = &config.rules[rule_idx].query else {
+        return false;
+    };
+    query.rule_names().into_iter().any(|name| {
+        config
+            .rule_idx(name)
+            .is_some_and(|idx| idx == input_idx || query_depends_on(config, idx, input_idx))
+    })
+}
+
+/// Returns the index of the first watch_dir of the rule that the path is under.
+pub fn watch_idx_for_path(config: &Config, rule_idx: usize, path: &Path) -> Option<usize> {
+    config.rules[rule_idx]
+        .watch_dirs
+        .iter()
+        .position(|watch_dir| path.starts_with(watch_dir))
+}
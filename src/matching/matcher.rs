@@ -0,0 +1,120 @@
+use std::{
+    cell::OnceCell,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::{self, Metadata},
+    path::Path,
+    sync::Arc,
+};
+
+use regex::Regex;
+
+use crate::{get_match_target, MatchOn, Result};
+
+// Matcher ////////////////////////////////////////////////////////////////////
+
+/// Decides whether a path is tagged for a rule, e.g. by matching its name against a regex.
+///
+/// Rules use their `regex` (see the `Vec<Regex>` impl), and any matchers registered in code
+/// that they name in `matchers` (see `Config::register_matcher`). A path is tagged if any of
+/// them matches.
+pub trait Matcher: fmt::Debug + Send + Sync {
+    /// Returns a `Match` if the path is tagged, or None.
+    fn matches(&self, input: &MatchInput) -> Result<Option<Match>>;
+}
+
+/// A successful match, with whatever the matcher captured, e.g. regex groups.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Match {
+    pub captures: BTreeMap<String, String>,
+}
+
+/// The path being matched, with what a matcher may need to know about it.
+#[derive(Debug)]
+pub struct MatchInput<'a> {
+    pub path: &'a Path,
+    /// The watch_dir the path is under.
+    pub watch_dir: &'a Path,
+    /// The rule's `match_on` setting.
+    pub match_on: MatchOn,
+    metadata: OnceCell<Option<Metadata>>,
+}
+
+impl<'a> MatchInput<'a> {
+    pub fn new(path: &'a Path, watch_dir: &'a Path, match_on: MatchOn) -> Self {
+        Self {
+            path,
+            watch_dir,
+            match_on,
+            metadata: OnceCell::new(),
+        }
+    }
+
+    /// The path's metadata, following symlinks, or None if it doesn't exist.
+    /// It's only read once, however many matchers ask for it.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata
+            .get_or_init(|| fs::metadata(self.path).ok())
+            .as_ref()
+    }
+
+    /// The part of the path chosen by `match_on`, see `get_match_target`.
+    pub fn target(&self) -> Result<&'a str> {
+        Ok(get_match_target(self.path, self.match_on, self.watch_dir)?)
+    }
+}
+
+/// Matches if any of the regexes matches the part of the path chosen by `match_on`.
+/// Captures the groups of the first one that does, by name and by index.
+impl Matcher for Vec<Regex> {
+    fn matches(&self, input: &MatchInput) -> Result<Option<Match>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let target = input.target()?;
+        let Some((regex, captures)) = self
+            .iter()
+            .find_map(|regex| Some((regex, regex.captures(target)?)))
+        else {
+            return Ok(None);
+        };
+
+        let mut found = Match::default();
+        for (idx, name) in regex.capture_names().enumerate() {
+            let Some(group) = captures.get(idx) else {
+                continue;
+            };
+            found
+                .captures
+                .insert(idx.to_string(), group.as_str().to_string());
+            if let Some(name) = name {
+                found
+                    .captures
+                    .insert(name.to_string(), group.as_str().to_string());
+            }
+        }
+
+        Ok(Some(found))
+    }
+}
+
+// Matchers ///////////////////////////////////////////////////////////////////
+
+/// The matchers registered in code, keyed by the name rules refer to them by.
+#[derive(Debug, Clone, Default)]
+pub struct Matchers(HashMap<String, Arc<dyn Matcher>>);
+
+impl Matchers {
+    /// Registers a matcher, replacing any already registered under the name.
+    pub fn insert(&mut self, name: impl Into<String>, matcher: impl Matcher + 'static) {
+        self.0.insert(name.into(), Arc::new(matcher));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn Matcher>> {
+        self.0.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+}
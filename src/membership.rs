@@ -0,0 +1,248 @@
+use std::{
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{
+    actions::tagging::{tag_source, untagged_path},
+    get_basename, rule_matches, symlink_target, watch_idx_for_path, Config,
+};
+
+// Membership /////////////////////////////////////////////////////////////////
+
+/// A source that's a member of a rule, as written by `filetags export`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Membership {
+    pub rule: String,
+    pub watch_dir: PathBuf,
+    /// The source, relative to `watch_dir`.
+    pub path: PathBuf,
+    /// The name of its link, relative to the link_dir.
+    pub link: PathBuf,
+}
+
+/// The file formats membership can be exported to and imported from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    /// Guesses the format from a file's extension, defaulting to JSON.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("csv") => ExportFormat::Csv,
+            _ => ExportFormat::Json,
+        }
+    }
+}
+
+/// Collects the membership of every rule, from the links in its link_dirs whose sources are
+/// still members.
+pub fn collect_membership(config: &Config) -> anyhow::Result<Vec<Membership>> {
+    let mut records = vec![];
+    for (rule_idx, rule) in config.rules.iter().enumerate() {
+        for link_dir in &rule.link_dirs {
+            for entry in WalkDir::new(link_dir) {
+                let entry = entry?;
+                if !entry.path_is_symlink() {
+                    continue;
+                }
+                let Some(src_path) = symlink_target(entry.path())? else {
+                    continue;
+                };
+                let Some(watch_idx) = watch_idx_for_path(config, rule_idx, &src_path) else {
+                    continue;
+                };
+                if !rule_matches(config, rule_idx, &src_path)? {
+                    continue;
+                }
+                let watch_dir = &rule.watch_dirs[watch_idx];
+                records.push(Membership {
+                    rule: rule.name.clone(),
+                    watch_dir: watch_dir.clone(),
+                    path: src_path.strip_prefix(watch_dir)?.to_path_buf(),
+                    link: entry.path().strip_prefix(link_dir)?.to_path_buf(),
+                });
+            }
+        }
+    }
+    records.sort();
+
+    Ok(records)
+}
+
+/// Writes membership records in the given format.
+pub fn write_membership(
+    records: &[Membership],
+    format: ExportFormat,
+    mut writer: impl Write,
+) -> anyhow::Result<()> {
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, records).context("writing JSON")?;
+            writeln!(writer)?;
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for record in records {
+                writer.serialize(record).context("writing CSV")?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads membership records in the given format.
+pub fn read_membership(reader: impl Read, format: ExportFormat) -> anyhow::Result<Vec<Membership>> {
+    match format {
+        ExportFormat::Json => serde_json::from_reader(reader).context("reading JSON"),
+        ExportFormat::Csv => csv::Reader::from_reader(reader)
+            .deserialize()
+            .collect::<Result<Vec<_>, _>>()
+            .context("reading CSV"),
+    }
+}
+
+// Import /////////////////////////////////////////////////////////////////////
+
+/// Replaces a root directory of imported paths, written as `FROM=TO`,
+/// e.g. `/home/alice/Samples=/mnt/nas/Samples`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathRemap {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl FromStr for PathRemap {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('=')
+            .with_context(|| format!("expected FROM=TO, got {:?}", s))?;
+        Ok(Self {
+            from: PathBuf::from(from),
+            to: PathBuf::from(to),
+        })
+    }
+}
+
+/// Applies the remap with the longest matching root to the path.
+pub fn remap_path(path: &Path, remaps: &[PathRemap]) -> PathBuf {
+    remaps
+        .iter()
+        .filter(|remap| path.starts_with(&remap.from))
+        .max_by_key(|remap| remap.from.components().count())
+        .and_then(|remap| {
+            path.strip_prefix(&remap.from)
+                .ok()
+                .map(|rest| remap.to.join(rest))
+        })
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// What `import_membership` did with each record.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Sources that were tagged, at their new paths.
+    pub tagged: Vec<PathBuf>,
+    /// Sources that were already members.
+    pub already_tagged: Vec<PathBuf>,
+    /// Sources that don't exist.
+    pub not_found: Vec<PathBuf>,
+    /// Records that couldn't be applied, and why.
+    pub skipped: Vec<(Membership, String)>,
+}
+
+/// Re-applies membership records, tagging each source the same way the daemon does for links
+/// dropped into a link_dir (see `tag_source`). The daemon then links them as usual.
+///
+/// Watch_dirs are remapped first, so that records can be imported on another machine. Sources
+/// that aren't found are also looked for under their untagged name, e.g. `kick.wav` for an
+/// exported `_kick.wav`.
+/// Records of query rules are skipped, since their membership follows from other rules, and so
+/// are records whose path is absolute or leads out of their watch_dir with `..`.
+pub fn import_membership(
+    config: &Config,
+    records: &[Membership],
+    remaps: &[PathRemap],
+) -> anyhow::Result<ImportReport> {
+    let mut report = ImportReport::default();
+    for record in records {
+        let Some(rule_idx) = config.rule_idx(&record.rule) else {
+            report
+                .skipped
+                .push((record.clone(), "no such rule".to_string()));
+            continue;
+        };
+        if config.rules[rule_idx].query.is_some() {
+            report
+                .skipped
+                .push((record.clone(), "query rules can't be tagged".to_string()));
+            continue;
+        }
+
+        if !record
+            .path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            report.skipped.push((
+                record.clone(),
+                "its path must be relative to the watch_dir, without `..`".to_string(),
+            ));
+            continue;
+        }
+
+        let mut src_path = remap_path(&record.watch_dir, remaps).join(&record.path);
+        if !src_path.exists() {
+            // the source may not be tagged yet here, so look for it under its untagged name
+            let watch_dir = remap_path(&record.watch_dir, remaps);
+            let untagged_path = untagged_path(&config.rules[rule_idx], &src_path, &watch_dir)
+                .filter(|path| path.exists());
+            match untagged_path {
+                Some(untagged_path) => src_path = untagged_path,
+                None => {
+                    report.not_found.push(src_path);
+                    continue;
+                }
+            }
+        }
+        if watch_idx_for_path(config, rule_idx, &src_path).is_none() {
+            report.skipped.push((
+                record.clone(),
+                format!("{:?} isn't under a watch_dir", src_path),
+            ));
+            continue;
+        }
+        if rule_matches(config, rule_idx, &src_path)? {
+            report.already_tagged.push(src_path);
+            continue;
+        }
+
+        let tagged_path = tag_source(config, None, rule_idx, &src_path)
+            .with_context(|| format!("tagging {:?}", src_path))?;
+        if rule_matches(config, rule_idx, &tagged_path)? {
+            report.tagged.push(tagged_path);
+        } else {
+            report.skipped.push((
+                record.clone(),
+                format!(
+                    "{:?} couldn't be tagged, is `cookie` set and its name free?",
+                    get_basename(&src_path)?
+                ),
+            ));
+        }
+    }
+
+    Ok(report)
+}
@@ -1,16 +1,23 @@
 use std::{
     fs::{self, Metadata},
-    path::{Path, PathBuf},
+    io,
+    path::{Component, Path, PathBuf},
     sync::Arc,
 };
 
 use anyhow::Context;
 use regex::Regex;
 use serde::{Serialize, Serializer};
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::{
+    mpsc::{UnboundedReceiver, UnboundedSender},
+    oneshot,
+};
 use tracing::debug;
 
-use crate::{Config, Message, Rule};
+use crate::{
+    actions::{Action, ActionReport},
+    Config, MatchOn, Message, Rule,
+};
 
 // generic helpers ////////////////////////////////////////////////////////////
 
@@ -120,34 +127,127 @@ pub fn get_basename(path: &Path) -> anyhow::Result<&str> {
     Ok(basename)
 }
 
+/// Returns whether a path is a glob pattern, e.g. `/media/*/Samples`, rather than a plain path.
+pub fn is_glob(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.contains(['*', '?', '[']))
+}
+
+/// Returns the directories a glob pattern matches, sorted. Unreadable paths are skipped.
+pub fn glob_dirs(pattern: &Path) -> Vec<PathBuf> {
+    let Some(pattern) = pattern.to_str() else {
+        return vec![];
+    };
+    let mut dirs = match glob::glob(pattern) {
+        Ok(paths) => paths
+            .filter_map(|path| path.ok())
+            .filter(|path| path.is_dir())
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            debug!("invalid glob {:?}: {}", pattern, e);
+            vec![]
+        }
+    };
+    dirs.sort();
+    dirs
+}
+
 /// Returns whether a path is a subdir of any from a list of paths.
 pub fn path_is_under_any_dirs(path: &Path, many_dirs: &[PathBuf]) -> anyhow::Result<bool> {
     Ok(many_dirs.iter().any(|d| path.starts_with(d)))
 }
 
-/// Returns whether a path filename matches a regex.
-pub fn path_matches_any_regex(path: &Path, regexes: &[Regex]) -> anyhow::Result<bool> {
-    let basename = get_basename(path)?;
+/// Returns the part of a path chosen by `match_on`, for matching regexes against.
+///
+/// `watch_dir` is the watch_dir the path is under, used for `MatchOn::RelativePath`.
+pub fn get_match_target<'a>(
+    path: &'a Path,
+    match_on: MatchOn,
+    watch_dir: &Path,
+) -> anyhow::Result<&'a str> {
+    let part = match match_on {
+        MatchOn::Basename => return get_basename(path),
+        MatchOn::Stem => path.file_stem().unwrap_or_default(),
+        MatchOn::Extension => path.extension().unwrap_or_default(),
+        MatchOn::RelativePath => path
+            .strip_prefix(watch_dir)
+            .with_context(|| format!("stripping watch_dir {:?} off {:?}", watch_dir, path))?
+            .as_os_str(),
+        MatchOn::AbsolutePath => path.as_os_str(),
+    };
+    part.to_str()
+        .with_context(|| format!("parsing {:?} of path to UTF-8 str: {:?}", match_on, path))
+}
+
+/// Returns the path with the part chosen by `match_on` replaced, the inverse of
+/// `get_match_target`. None if the part can't be replaced, e.g. a path without a parent.
+pub fn replace_match_target(
+    path: &Path,
+    match_on: MatchOn,
+    watch_dir: &Path,
+    target: &str,
+) -> Option<PathBuf> {
+    match match_on {
+        MatchOn::Basename => Some(path.parent()?.join(target)),
+        MatchOn::Stem => {
+            let mut name = target.to_string();
+            if let Some(extension) = path.extension() {
+                name = format!("{}.{}", name, extension.to_str()?);
+            }
+            Some(path.parent()?.join(name))
+        }
+        MatchOn::Extension => Some(path.with_extension(target)),
+        MatchOn::RelativePath => Some(watch_dir.join(target)),
+        MatchOn::AbsolutePath => Some(PathBuf::from(target)),
+    }
+}
+
+/// Returns whether the part of a path chosen by `match_on` matches any of the regexes.
+pub fn path_matches_any_regex(
+    path: &Path,
+    regexes: &[Regex],
+    match_on: MatchOn,
+    watch_dir: &Path,
+) -> anyhow::Result<bool> {
+    let target = get_match_target(path, match_on, watch_dir)?;
 
-    Ok(regexes.iter().any(|r| r.is_match(basename)))
+    Ok(regexes.iter().any(|r| r.is_match(target)))
 }
 
 /// Returns the target path of a symlink, or None if symlink is broken.
+/// A relative target is returned resolved against the directory of the symlink.
 pub fn symlink_target(path: &Path) -> anyhow::Result<Option<PathBuf>> {
     if let Ok(target_path) = fs::read_link(path) {
         if target_path.is_absolute() && fs::metadata(&target_path).is_ok() {
             return Ok(Some(target_path));
         }
         let dirname = path.parent().unwrap_or_else(|| Path::new(""));
-        let resolved = dirname.join(&target_path);
-        if fs::metadata(resolved).is_ok() {
-            return Ok(Some(target_path));
+        let resolved = normalize_path(&dirname.join(&target_path));
+        if fs::metadata(&resolved).is_ok() {
+            return Ok(Some(resolved));
         }
     }
     debug!("Symlink is broken: {:?}", path);
     Ok(None)
 }
 
+/// Removes the `.` and `..` components of a path without touching the filesystem, so that a
+/// relative symlink target resolved against the symlink's dir can be compared to other paths.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
 /// Given an src_dir, calculates a path for where to create a symlink that points to it.
 ///
 /// This is done by stripping the watch_dir prefix off the src_path,
@@ -165,6 +265,37 @@ pub fn calc_link_from_src_orig(
     Ok(link)
 }
 
+/// Returns the path `to`, relative to the directory `from_dir`, e.g. `../Samples/_kick.wav`.
+/// Both paths are expected to be absolute.
+pub fn relative_path(from_dir: &Path, to: &Path) -> PathBuf {
+    let from = from_dir.components().collect::<Vec<_>>();
+    let to = to.components().collect::<Vec<_>>();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for component in &to[common..] {
+        path.push(component);
+    }
+    path
+}
+
+/// Writes the file through a temporary file next to it, so that readers never see it
+/// half-written. Parent directories are created as needed.
+pub fn write_file_atomically(path: &Path, contents: impl AsRef<[u8]>) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("creating parent dirs of: {:?}", path))?;
+    }
+    let mut tmp_name = path.file_name().context("getting basename")?.to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    fs::write(&tmp_path, contents).with_context(|| format!("writing {:?}", tmp_path))?;
+    fs::rename(&tmp_path, path).with_context(|| format!("renaming {:?} to {:?}", tmp_path, path))
+}
+
 /// Deletes the symlink at the specified path.
 /// If it's not a symlink, return an error.
 pub fn delete_symlink(path: &Path, metadata: &Metadata) -> anyhow::Result<()> {
@@ -185,6 +316,15 @@ pub fn send_shutdown(tx: &Sender<Message>) {
         .expect("failed to shutdown, crashing program");
 }
 
+/// Sends an action to the corresponding Receiver, and returns a receiver for its report (see
+/// `Dispatcher::request`).
+pub fn send_request(tx: &Sender<Message>, action: Action) -> oneshot::Receiver<ActionReport> {
+    let (completion, report) = oneshot::channel();
+    tx.send(Message::Request(action, completion))
+        .expect("failed to send request, crashing program");
+    report
+}
+
 // Notify helpers /////////////////////////////////////////////////////////////
 
 /// Matches Notify event kinds on which to perform a filename cookie check on.
@@ -195,6 +335,168 @@ macro_rules! match_event_kinds {
     };
 }
 
+/// Matches Notify event kinds after which links may have been left pointing to nothing.
+#[macro_export]
+macro_rules! match_removal_event_kinds {
+    () => {
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) | EventKind::Remove(_)
+    };
+}
+
+/// Matches Notify event kinds for changed metadata, which includes extended attributes.
+#[macro_export]
+macro_rules! match_metadata_event_kinds {
+    () => {
+        EventKind::Modify(ModifyKind::Metadata(
+            MetadataKind::Any | MetadataKind::Extended | MetadataKind::Other,
+        ))
+    };
+}
+
+// xattr helpers //////////////////////////////////////////////////////////////
+
+/// Reads a comma-separated list of tags from an extended attribute, such as `user.xdg.tags`.
+///
+/// Returns no tags if the attribute isn't set, or the filesystem doesn't support xattrs.
+pub fn read_xattr_tags(path: &Path, name: &str) -> anyhow::Result<Vec<String>> {
+    let value = match xattr::get(path, name) {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+            debug!("xattrs not supported on path: {:?}", path);
+            vec![]
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("reading xattr {:?} of path: {:?}", name, path))
+        }
+    };
+    let value = String::from_utf8_lossy(&value);
+
+    Ok(split_tags(&value).collect())
+}
+
+/// Adds a tag to the comma-separated list of tags in an extended attribute, if not present.
+/// Returns whether it was added.
+pub fn add_xattr_tag(path: &Path, name: &str, tag: &str) -> anyhow::Result<bool> {
+    let mut tags = read_xattr_tags(path, name)?;
+    if tags.iter().any(|t| t == tag) {
+        return Ok(false);
+    }
+    tags.push(tag.to_string());
+    write_xattr_tags(path, name, &tags)?;
+    Ok(true)
+}
+
+/// Removes a tag from the comma-separated list of tags in an extended attribute, if present.
+/// The attribute is removed entirely once it has no tags left.
+pub fn remove_xattr_tag(path: &Path, name: &str, tag: &str) -> anyhow::Result<()> {
+    let mut tags = read_xattr_tags(path, name)?;
+    let len = tags.len();
+    tags.retain(|t| t != tag);
+    if tags.len() != len {
+        write_xattr_tags(path, name, &tags)?;
+    }
+    Ok(())
+}
+
+fn write_xattr_tags(path: &Path, name: &str, tags: &[String]) -> anyhow::Result<()> {
+    if tags.is_empty() {
+        xattr::remove(path, name)
+    } else {
+        xattr::set(path, name, tags.join(",").as_bytes())
+    }
+    .with_context(|| format!("writing xattr {:?} of path: {:?}", name, path))
+}
+
+// sidecar helpers ////////////////////////////////////////////////////////////
+
+/// A per-directory sidecar file, listing `filename: tag1, tag2` per line.
+pub const SIDECAR_DIR_FILE: &str = ".filetags";
+
+/// The extension of a per-file sidecar file, e.g. `kick.wav.tags`, listing comma or newline
+/// separated tags.
+pub const SIDECAR_EXTENSION: &str = "tags";
+
+/// Matches Notify event kinds for files written in place, relevant for sidecar files.
+#[macro_export]
+macro_rules! match_write_event_kinds {
+    () => {
+        EventKind::Modify(ModifyKind::Data(_))
+            | EventKind::Access(AccessKind::Close(AccessMode::Write))
+    };
+}
+
+/// Returns whether the path is a sidecar file.
+pub fn is_sidecar(path: &Path) -> bool {
+    path.file_name().is_some_and(|n| n == SIDECAR_DIR_FILE)
+        || path.extension().is_some_and(|e| e == SIDECAR_EXTENSION)
+}
+
+/// Returns the paths whose tags are declared by a sidecar file.
+///
+/// For a per-directory sidecar, that's every non-sidecar entry in its directory.
+pub fn sidecar_described_paths(sidecar_path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if sidecar_path
+        .file_name()
+        .is_some_and(|n| n == SIDECAR_DIR_FILE)
+    {
+        let dir = sidecar_path.parent().context("getting parent of sidecar")?;
+        Ok(fs::read_dir(dir)
+            .with_context(|| format!("reading dir: {:?}", dir))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| !is_sidecar(p))
+            .collect())
+    } else {
+        Ok(vec![sidecar_path.with_extension("")])
+    }
+}
+
+/// Reads the tags declared for a path in its sidecar files, either in the `.filetags` of its
+/// directory or in `<file>.tags` next to it.
+pub fn read_sidecar_tags(path: &Path) -> anyhow::Result<Vec<String>> {
+    let mut tags = vec![];
+    let basename = get_basename(path)?;
+
+    let dir_sidecar = path
+        .parent()
+        .context("getting parent of path")?
+        .join(SIDECAR_DIR_FILE);
+    if let Some(contents) = read_optional_file(&dir_sidecar)? {
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some((filename, line_tags)) = line.rsplit_once(':') {
+                if filename.trim() == basename {
+                    tags.extend(split_tags(line_tags));
+                }
+            }
+        }
+    }
+
+    let file_sidecar = path.with_file_name(format!("{}.{}", basename, SIDECAR_EXTENSION));
+    if let Some(contents) = read_optional_file(&file_sidecar)? {
+        tags.extend(contents.lines().flat_map(split_tags));
+    }
+
+    Ok(tags)
+}
+
+fn split_tags(s: &str) -> impl Iterator<Item = String> + '_ {
+    s.split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+}
+
+/// Reads a file to a string, or returns None if it doesn't exist.
+fn read_optional_file(path: &Path) -> anyhow::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("reading file: {:?}", path)),
+    }
+}
+
 // logger addons //////////////////////////////////////////////////////////////
 
 /// Creates and enters a new Tracing span.
@@ -106,12 +106,17 @@ macro_rules! create_config {
             ],
             ..filetags::RawConfig::default()
         };
-        let serialized_str = serde_yml::to_string(&raw_config).unwrap();
-        let deserialized_config: Config = serde_yml::from_str(&serialized_str).unwrap();
-        Arc::new(deserialized_config)
+        config_from_raw(&raw_config)
     }}
 }
 
+/// Round-trips a RawConfig through yaml into a Config, the same way a config file is loaded.
+pub fn config_from_raw(raw_config: &filetags::RawConfig) -> std::sync::Arc<filetags::Config> {
+    let serialized_str = serde_yml::to_string(raw_config).unwrap();
+    let deserialized_config: filetags::Config = serde_yml::from_str(&serialized_str).unwrap();
+    std::sync::Arc::new(deserialized_config)
+}
+
 pub fn rename_file(orig: &Path, new: &Path) {
     // rename file3 to file3_renamed
     std::fs::rename(orig, new).expect("failed to rename file");
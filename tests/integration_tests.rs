@@ -1,8 +1,25 @@
 mod common;
 
-use std::{fs, sync::Arc, thread, time::Duration};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
 
-use filetags::{clone_vars, run_with_config, send_shutdown, Config, Logger, Message, Rule};
+use filetags::{
+    actions::{deduplicating::find_duplicates, Action},
+    clone_vars, collect_membership,
+    events::Event,
+    import_membership, read_membership, run_config_command, run_with_config, send_request,
+    send_shutdown, write_membership, Age, Args, Config, ConfigCommand, ConfigFormat, Dedupe,
+    Dispatcher, Error, ExportFormat, HashCache, Logger, Match, MatchInput, MatchOn, Matcher,
+    Membership, Message, MimePattern, MiscSettings, PathRemap, Playlist, PlaylistSort, RawConfig,
+    RawRuleSettings, RecallPolicy, Rule, RuleSettings, Targets,
+};
 use regex::Regex;
 
 use common::*;
@@ -52,7 +69,6 @@ async fn basic1() {
     let test_hook = {
         clone_vars!(tx, file3, file3_renamed, file4);
         move || {
-            thread::sleep(Duration::from_millis(100));
             rename_file(&file3, &file3_renamed);
             fs::File::create(&file4).expect("failed to create files");
 
@@ -118,8 +134,7 @@ async fn basic2() {
     let test_hook = {
         clone_vars!(tx);
         move || {
-            // TODO: have it check for corresponding logs instead of waiting 1/10 a sec
-            thread::sleep(Duration::from_millis(500));
+            // startup has finished by the time the hook runs
             send_shutdown(&tx);
         }
     };
@@ -140,3 +155,2668 @@ async fn basic2() {
         ],
     );
 }
+
+#[tokio::test]
+async fn clean_by_target() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_clean_by_target").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // linked by the init scan, and kept
+        (kept = watch_dir / "_kept.txt" : create = "f"),
+
+        // not a member, so the link to it is deleted at startup, even though its name matches
+        (plain = watch_dir / "plain.txt"          : create = "f"),
+        (plain_symlink = link_dir / "_plain.txt"  : create = "symlink" -> plain),
+
+        // the test_hook will delete this file, expect its link to be deleted
+        (gone = watch_dir / "_gone.txt" : create = "f"),
+    );
+    // a relative symlink to a member, whose name doesn't match, is kept
+    std::os::unix::fs::symlink("../watch_dir/_kept.txt", link_dir.join("renamed.txt"))
+        .expect("failed to create symlink");
+
+    // define config
+    let config = create_config!(("test", (watch_dir), (link_dir), "^_.*"));
+
+    let test_hook = {
+        clone_vars!(tx, gone);
+        move || {
+            fs::remove_file(&gone).expect("failed to remove file");
+
+            thread::sleep(Duration::from_millis(500));
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "link_dir",
+            "link_dir/_kept.txt",
+            "link_dir/renamed.txt",
+            "watch_dir",
+            "watch_dir/_kept.txt",
+            "watch_dir/plain.txt",
+        ],
+    );
+    assert_eq!(
+        fs::read_link(link_dir.join("renamed.txt")).unwrap(),
+        PathBuf::from("../watch_dir/_kept.txt")
+    );
+}
+
+#[tokio::test]
+async fn link_name_conflicts() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_link_name_conflicts").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (pack_a = watch_dir / "pack_a"  : create = "dir"),
+        (pack_b = watch_dir / "pack_b"  : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // both want the link name "_kick.wav", expect the second to be linked as "0__kick.wav"
+        // in the link_dir, and nothing to be created next to the first
+        (kick_a = pack_a / "_kick.wav" : create = "f"),
+        (kick_b = pack_b / "_kick.wav" : create = "f"),
+    );
+
+    // define config
+    let config = create_config!(("test", (watch_dir), (link_dir), "^_.*"));
+
+    let test_hook = {
+        clone_vars!(tx);
+        move || {
+            // startup has finished by the time the hook runs
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "link_dir",
+            "link_dir/0__kick.wav",
+            "link_dir/_kick.wav",
+            "watch_dir",
+            "watch_dir/pack_a",
+            "watch_dir/pack_a/_kick.wav",
+            "watch_dir/pack_b",
+            "watch_dir/pack_b/_kick.wav",
+        ],
+    );
+    let mut targets = vec![
+        fs::read_link(link_dir.join("_kick.wav")).unwrap(),
+        fs::read_link(link_dir.join("0__kick.wav")).unwrap(),
+    ];
+    targets.sort();
+    assert_eq!(targets, vec![kick_a, kick_b]);
+}
+
+#[tokio::test]
+async fn query_rule() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_query_rule").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (fav_dir = root / "fav_dir"     : create = "dir"),
+        (drums_dir = root / "drums_dir" : create = "dir"),
+        (query_dir = root / "query_dir" : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // fav and drums, expect the init scan to link it in all three link_dirs
+        (file1 = watch_dir / "_kick.wav" : create = "f"),
+
+        // only drums, expect no link in query_dir
+        (file2 = watch_dir / "snare.wav" : create = "f"),
+        (file2_renamed = watch_dir / "_snare.wav" : create = "no"),
+
+        // fav but also a loop, expect no link in query_dir
+        (file3 = watch_dir / "_loop.wav" : create = "f"),
+
+        // stale link in query_dir, expect the cleaner to delete it
+        (file4 = watch_dir / "_pad.wav" : create = "f"),
+        (file4_symlink = query_dir / "_pad.wav" : create = "symlink" -> file4),
+    );
+
+    // define config
+    let raw_config = RawConfig {
+        rules: vec![
+            Rule {
+                name: "fav".into(),
+                watch_dirs: vec![watch_dir.clone()],
+                link_dirs: vec![fav_dir.clone()],
+                regex: vec![Regex::new("^_").unwrap()],
+                ..Rule::default()
+            },
+            Rule {
+                name: "drums".into(),
+                watch_dirs: vec![watch_dir.clone()],
+                link_dirs: vec![drums_dir.clone()],
+                regex: vec![Regex::new("kick|snare|loop").unwrap()],
+                ..Rule::default()
+            },
+            Rule {
+                name: "fav drums".into(),
+                link_dirs: vec![query_dir.clone()],
+                query: Some("fav AND drums AND NOT any of loops".parse().unwrap()),
+                ..Rule::default()
+            },
+            Rule {
+                name: "loops".into(),
+                watch_dirs: vec![watch_dir.clone()],
+                link_dirs: vec![],
+                regex: vec![Regex::new("loop").unwrap()],
+                ..Rule::default()
+            },
+        ],
+        ..RawConfig::default()
+    };
+    let config = config_from_raw(&raw_config);
+    assert_eq!(config.rules[2].watch_dirs, vec![watch_dir.clone()]);
+
+    let test_hook = {
+        clone_vars!(tx, file2, file2_renamed);
+        move || {
+            // becomes a fav, expect Notify to link it in query_dir
+            rename_file(&file2, &file2_renamed);
+
+            thread::sleep(Duration::from_millis(500));
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "drums_dir",
+            "drums_dir/_kick.wav",
+            "drums_dir/_loop.wav",
+            "drums_dir/_snare.wav",
+            "fav_dir",
+            "fav_dir/_kick.wav",
+            "fav_dir/_loop.wav",
+            "fav_dir/_pad.wav",
+            "fav_dir/_snare.wav",
+            "query_dir",
+            "query_dir/_kick.wav",
+            "query_dir/_snare.wav",
+            "watch_dir",
+            "watch_dir/_kick.wav",
+            "watch_dir/_loop.wav",
+            "watch_dir/_pad.wav",
+            "watch_dir/_snare.wav",
+        ],
+    );
+}
+
+#[tokio::test]
+async fn tagged_dirs() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_tagged_dirs").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+        (pack = watch_dir / "_808 Pack" : create = "dir"),
+        (other = watch_dir / "other"    : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // inside a tagged dir, expect only the dir to be linked
+        (file1 = pack / "_kick.wav" : create = "f"),
+        (file2 = pack / "snare.wav" : create = "f"),
+
+        // a stale link to a file inside the tagged dir, expect the cleaner to delete it
+        (file1_symlink = link_dir / "_kick.wav" : create = "symlink" -> file1),
+
+        // tagged file elsewhere, expect a link
+        (file3 = other / "_hat.wav" : create = "f"),
+    );
+
+    // define config
+    let mut raw_config = RawConfig {
+        rules: vec![Rule {
+            name: "test".into(),
+            watch_dirs: vec![watch_dir.clone()],
+            link_dirs: vec![link_dir.clone()],
+            regex: vec![Regex::new("^_").unwrap()],
+            ..Rule::default()
+        }],
+        ..RawConfig::default()
+    };
+    raw_config.default_rule_settings.targets = Targets::Both;
+    let config = config_from_raw(&raw_config);
+
+    let test_hook = {
+        clone_vars!(tx);
+        move || {
+            // startup has finished by the time the hook runs
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "link_dir",
+            "link_dir/_808 Pack",
+            "link_dir/_hat.wav",
+            "watch_dir",
+            "watch_dir/_808 Pack",
+            "watch_dir/_808 Pack/_kick.wav",
+            "watch_dir/_808 Pack/snare.wav",
+            "watch_dir/other",
+            "watch_dir/other/_hat.wav",
+        ],
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn inherit_from_parents() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_inherit_from_parents").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir"         : create = "dir"),
+        (link_dir = root / "link_dir"           : create = "dir"),
+        (best_of = watch_dir / "_best-of"       : create = "dir"),
+        (nested = best_of / "nested"            : create = "dir"),
+        (keep = watch_dir / "keep"              : create = "dir"),
+        (keep_renamed = watch_dir / "_keep"     : create = "no"),
+        (drop = watch_dir / "_drop"             : create = "dir"),
+        (drop_renamed = watch_dir / "drop"      : create = "no"),
+    );
+
+    // create files
+    let_paths!(
+        // inside a tagged dir, expect the init scan to link them
+        (file1 = best_of / "kick.wav" : create = "f"),
+        (file2 = nested / "snare.wav" : create = "f"),
+
+        // the test_hook will tag the parent dir, expect Notify to link it
+        (file3 = keep / "hat.wav" : create = "f"),
+
+        // the test_hook will untag the parent dir, expect Notify to remove the link
+        (file4 = drop / "tom.wav" : create = "f"),
+    );
+
+    // define config
+    let mut raw_config = RawConfig {
+        rules: vec![Rule {
+            name: "test".into(),
+            watch_dirs: vec![watch_dir.clone()],
+            link_dirs: vec![link_dir.clone()],
+            regex: vec![Regex::new("^_").unwrap()],
+            ..Rule::default()
+        }],
+        ..RawConfig::default()
+    };
+    raw_config.default_rule_settings.inherit_from_parents = true;
+    let config = config_from_raw(&raw_config);
+
+    let test_hook = {
+        clone_vars!(tx, keep, keep_renamed, drop, drop_renamed);
+        move || {
+            rename_file(&keep, &keep_renamed);
+            rename_file(&drop, &drop_renamed);
+
+            thread::sleep(Duration::from_millis(500));
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "link_dir",
+            "link_dir/kick.wav",
+            "link_dir/snare.wav",
+            "link_dir/hat.wav",
+            "watch_dir",
+            "watch_dir/_best-of",
+            "watch_dir/_best-of/kick.wav",
+            "watch_dir/_best-of/nested",
+            "watch_dir/_best-of/nested/snare.wav",
+            "watch_dir/_keep",
+            "watch_dir/_keep/hat.wav",
+            "watch_dir/drop",
+            "watch_dir/drop/tom.wav",
+        ],
+    );
+}
+
+#[tokio::test]
+async fn match_on_relative_path() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_match_on_relative_path").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir"         : create = "dir"),
+        (link_dir = root / "link_dir"           : create = "dir"),
+        (one_shots = watch_dir / "one-shots"    : create = "dir"),
+        (loops = watch_dir / "loops"            : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // tagged and in a one-shots dir, expect a link
+        (file1 = one_shots / "_kick.wav" : create = "f"),
+
+        // untagged, expect no link
+        (file2 = one_shots / "snare.wav" : create = "f"),
+
+        // tagged but not in a one-shots dir, expect no link
+        (file3 = loops / "_loop.wav" : create = "f"),
+    );
+
+    // define config
+    let mut raw_config = RawConfig {
+        rules: vec![Rule {
+            name: "test".into(),
+            watch_dirs: vec![watch_dir.clone()],
+            link_dirs: vec![link_dir.clone()],
+            regex: vec![Regex::new("(^|/)one-shots/_[^/]*$").unwrap()],
+            ..Rule::default()
+        }],
+        ..RawConfig::default()
+    };
+    raw_config.default_rule_settings.match_on = MatchOn::RelativePath;
+    let config = config_from_raw(&raw_config);
+
+    let test_hook = {
+        clone_vars!(tx);
+        move || {
+            // startup has finished by the time the hook runs
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "link_dir",
+            "link_dir/_kick.wav",
+            "watch_dir",
+            "watch_dir/loops",
+            "watch_dir/loops/_loop.wav",
+            "watch_dir/one-shots",
+            "watch_dir/one-shots/_kick.wav",
+            "watch_dir/one-shots/snare.wav",
+        ],
+    );
+}
+
+#[tokio::test]
+async fn predicates() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_predicates").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir"   : create = "dir"),
+        (audio_dir = root / "audio_dir"   : create = "dir"),
+        (images_dir = root / "images_dir" : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // small wav, expect a link in audio_dir
+        (file1 = watch_dir / "_small.WAV" : create = "no"),
+        // too big, expect no link
+        (file2 = watch_dir / "_big.wav" : create = "no"),
+        // wrong extension, expect no link
+        (file3 = watch_dir / "_small.txt" : create = "no"),
+        // a png by its magic bytes, expect a link in images_dir
+        (file4 = watch_dir / "_cover.bin" : create = "no"),
+        // stale link to a file that grew too big, expect the cleaner to delete it
+        (file2_symlink = audio_dir / "_big.wav" : create = "no"),
+    );
+    fs::write(&file1, [0; 5]).unwrap();
+    fs::write(&file2, [0; 100]).unwrap();
+    fs::write(&file3, [0; 5]).unwrap();
+    fs::write(&file4, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+    create_symlinks!((file2, file2_symlink));
+
+    // define config
+    let raw_config = RawConfig {
+        rules: vec![
+            Rule {
+                name: "small audio".into(),
+                watch_dirs: vec![watch_dir.clone()],
+                link_dirs: vec![audio_dir.clone()],
+                regex: vec![Regex::new("^_").unwrap()],
+                max_size: Some("10B".parse().unwrap()),
+                extensions: vec!["wav".into(), ".flac".into()],
+                ..Rule::default()
+            },
+            Rule {
+                name: "images".into(),
+                watch_dirs: vec![watch_dir.clone()],
+                link_dirs: vec![images_dir.clone()],
+                regex: vec![Regex::new("^_").unwrap()],
+                mime: Some(MimePattern("image/*".into())),
+                newer_than: Some("30d".parse().unwrap()),
+                ..Rule::default()
+            },
+        ],
+        ..RawConfig::default()
+    };
+    let config = config_from_raw(&raw_config);
+
+    let test_hook = {
+        clone_vars!(tx);
+        move || {
+            // startup has finished by the time the hook runs
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "audio_dir",
+            "audio_dir/_small.WAV",
+            "images_dir",
+            "images_dir/_cover.bin",
+            "watch_dir",
+            "watch_dir/_big.wav",
+            "watch_dir/_cover.bin",
+            "watch_dir/_small.WAV",
+            "watch_dir/_small.txt",
+        ],
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn xattr_tags() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_xattr_tags").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // tagged, expect the init scan to link it, then the test_hook will untag it
+        (file1 = watch_dir / "kick.wav" : create = "f"),
+        // the test_hook will tag it, expect Notify to link it
+        (file2 = watch_dir / "snare.wav" : create = "f"),
+        // tagged with something else, expect no link
+        (file3 = watch_dir / "hat.wav" : create = "f"),
+    );
+    xattr::set(&file1, "user.xdg.tags", b"drums,fav").unwrap();
+    xattr::set(&file3, "user.xdg.tags", b"favorite").unwrap();
+
+    // define config
+    let raw_config = RawConfig {
+        rules: vec![Rule {
+            name: "test".into(),
+            watch_dirs: vec![watch_dir.clone()],
+            link_dirs: vec![link_dir.clone()],
+            xattr_tag: Some("fav".into()),
+            ..Rule::default()
+        }],
+        ..RawConfig::default()
+    };
+    let config = config_from_raw(&raw_config);
+
+    let test_hook = {
+        clone_vars!(tx, file1, file2);
+        move || {
+            xattr::set(&file1, "user.xdg.tags", b"drums").unwrap();
+            xattr::set(&file2, "user.xdg.tags", b"fav").unwrap();
+
+            thread::sleep(Duration::from_millis(500));
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "link_dir",
+            "link_dir/snare.wav",
+            "watch_dir",
+            "watch_dir/hat.wav",
+            "watch_dir/kick.wav",
+            "watch_dir/snare.wav",
+        ],
+    );
+}
+
+#[tokio::test]
+async fn write_xattr_tags() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_write_xattr_tags").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // tagged, expect a link and the tag to be written
+        (file1 = watch_dir / "_kick.wav" : create = "f"),
+        // untagged but linked, and tagged by the user, expect the link to be removed and the
+        // tag kept
+        (file2 = watch_dir / "snare.wav" : create = "f"),
+        (file2_symlink = link_dir / "snare.wav" : create = "symlink" -> file2),
+        // declared in a sidecar, expect the tag to be written, then removed once the test_hook
+        // deletes the sidecar
+        (file3 = watch_dir / "hat.wav" : create = "f"),
+        (file3_sidecar = watch_dir / "hat.wav.tags" : create = "no"),
+        // the same, but tagged by the user, expect the tag to be kept
+        (file4 = watch_dir / "ride.wav" : create = "f"),
+        (file4_sidecar = watch_dir / "ride.wav.tags" : create = "no"),
+    );
+    xattr::set(&file2, "user.xdg.tags", b"drums,favorites").unwrap();
+    xattr::set(&file4, "user.xdg.tags", b"favorites").unwrap();
+    fs::write(&file3_sidecar, "fav\n").unwrap();
+    fs::write(&file4_sidecar, "fav\n").unwrap();
+
+    // define config
+    let mut raw_config = RawConfig {
+        rules: vec![Rule {
+            name: "test".into(),
+            watch_dirs: vec![watch_dir.clone()],
+            link_dirs: vec![link_dir.clone()],
+            regex: vec![Regex::new("^_").unwrap()],
+            sidecar_tag: Some("fav".into()),
+            write_tag: Some("favorites".into()),
+            ..Rule::default()
+        }],
+        ..RawConfig::default()
+    };
+    raw_config.default_rule_settings.write_xattr_tags = true;
+    let config = config_from_raw(&raw_config);
+
+    // writing the tag that makes sources members would keep them members for good
+    let mut same_tag_config = raw_config.clone();
+    same_tag_config.rules[0].xattr_tag = Some("favorites".into());
+    assert!(matches!(
+        Config::from_raw(same_tag_config),
+        Err(Error::Config(_))
+    ));
+
+    let test_hook = {
+        clone_vars!(tx, file3, file3_sidecar, file4_sidecar);
+        move || {
+            // startup has finished by the time the hook runs
+            assert_eq!(
+                xattr::get(&file3, "user.xdg.tags").unwrap(),
+                Some(b"favorites".to_vec())
+            );
+            fs::remove_file(&file3_sidecar).unwrap();
+            fs::remove_file(&file4_sidecar).unwrap();
+
+            thread::sleep(Duration::from_millis(500));
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "link_dir",
+            "link_dir/_kick.wav",
+            "watch_dir",
+            "watch_dir/_kick.wav",
+            "watch_dir/hat.wav",
+            "watch_dir/ride.wav",
+            "watch_dir/snare.wav",
+        ],
+    );
+    assert_eq!(
+        xattr::get(&file1, "user.xdg.tags").unwrap(),
+        Some(b"favorites".to_vec())
+    );
+    assert_eq!(
+        xattr::get(&file2, "user.xdg.tags").unwrap(),
+        Some(b"drums,favorites".to_vec())
+    );
+    assert_eq!(xattr::get(&file3, "user.xdg.tags").unwrap(), None);
+    assert_eq!(
+        xattr::get(&file4, "user.xdg.tags").unwrap(),
+        Some(b"favorites".to_vec())
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sidecar_tags() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_sidecar_tags").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // declared in .filetags, expect the init scan to link it,
+        // then the test_hook will remove it from .filetags
+        (file1 = watch_dir / "kick.wav" : create = "f"),
+        // the test_hook will create a .tags sidecar for it, expect Notify to link it
+        (file2 = watch_dir / "_snare.wav" : create = "f"),
+        (file2_sidecar = watch_dir / "_snare.wav.tags" : create = "no"),
+        // declared with another tag, expect no link
+        (file3 = watch_dir / "hat.wav" : create = "f"),
+        (dir_sidecar = watch_dir / ".filetags" : create = "no"),
+    );
+    fs::write(&dir_sidecar, "kick.wav: drums, fav\nhat.wav: drums\n").unwrap();
+
+    // define config
+    let raw_config = RawConfig {
+        rules: vec![Rule {
+            name: "test".into(),
+            watch_dirs: vec![watch_dir.clone()],
+            link_dirs: vec![link_dir.clone()],
+            sidecar_tag: Some("fav".into()),
+            ..Rule::default()
+        }],
+        ..RawConfig::default()
+    };
+    let config = config_from_raw(&raw_config);
+
+    let test_hook = {
+        clone_vars!(tx, dir_sidecar, file2_sidecar);
+        move || {
+            fs::write(&dir_sidecar, "kick.wav: drums\nhat.wav: drums\n").unwrap();
+            fs::write(&file2_sidecar, "fav\n").unwrap();
+
+            thread::sleep(Duration::from_millis(500));
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "link_dir",
+            "link_dir/_snare.wav",
+            "watch_dir",
+            "watch_dir/.filetags",
+            "watch_dir/_snare.wav",
+            "watch_dir/_snare.wav.tags",
+            "watch_dir/hat.wav",
+            "watch_dir/kick.wav",
+        ],
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn untag_on_link_delete() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_untag_on_link_delete").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+        (stem_link_dir = root / "stem_link_dir" : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // the test_hook will delete its link, expect the source to be untagged
+        (file1 = watch_dir / "_kick.wav" : create = "f"),
+        (file1_symlink = link_dir / "_kick.wav" : create = "no"),
+        // untouched, expect its link to stay
+        (file2 = watch_dir / "_snare.wav" : create = "f"),
+        // matched on its stem, expect the matched part of the stem to be stripped
+        (file3 = watch_dir / "hat-fav.wav" : create = "f"),
+        (file3_symlink = stem_link_dir / "hat-fav.wav" : create = "no"),
+    );
+
+    // define config
+    let mut raw_config = RawConfig {
+        rules: vec![
+            Rule {
+                name: "test".into(),
+                watch_dirs: vec![watch_dir.clone()],
+                link_dirs: vec![link_dir.clone()],
+                regex: vec![Regex::new("^_").unwrap()],
+                ..Rule::default()
+            },
+            Rule {
+                name: "stem".into(),
+                watch_dirs: vec![watch_dir.clone()],
+                link_dirs: vec![stem_link_dir.clone()],
+                regex: vec![Regex::new("-fav$").unwrap()],
+                raw_settings: Some(RawRuleSettings {
+                    match_on: Some(MatchOn::Stem),
+                    ..RawRuleSettings::default()
+                }),
+                ..Rule::default()
+            },
+        ],
+        ..RawConfig::default()
+    };
+    raw_config.default_rule_settings.untag_on_link_delete = true;
+    let config = config_from_raw(&raw_config);
+
+    let test_hook = {
+        clone_vars!(tx, file1_symlink, file3_symlink);
+        move || {
+            fs::remove_file(&file1_symlink).expect("failed to remove link");
+            fs::remove_file(&file3_symlink).expect("failed to remove link");
+
+            thread::sleep(Duration::from_millis(500));
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "link_dir",
+            "link_dir/_snare.wav",
+            "stem_link_dir",
+            "watch_dir",
+            "watch_dir/_snare.wav",
+            "watch_dir/hat.wav",
+            "watch_dir/kick.wav",
+        ],
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tag_on_link_drop() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_tag_on_link_drop").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // the test_hook will drop a symlink to it, expect it to be tagged and linked
+        (file1 = watch_dir / "kick.wav" : create = "f"),
+        (file1_dropped = link_dir / "my kick" : create = "no"),
+        // the test_hook will move it into the link_dir, expect it to be returned and tagged
+        (file2 = watch_dir / "snare.wav" : create = "f"),
+        (file2_moved = link_dir / "snare.wav" : create = "no"),
+        // its tagged name is taken (by a dir, which isn't linked), expect the dropped symlink
+        // to be left in place
+        (file3 = watch_dir / "hat.wav" : create = "f"),
+        (_dir1 = watch_dir / "_hat.wav" : create = "dir"),
+        (file3_dropped = link_dir / "my hat" : create = "no"),
+    );
+
+    // define config
+    let mut raw_config = RawConfig {
+        rules: vec![Rule {
+            name: "test".into(),
+            watch_dirs: vec![watch_dir.clone()],
+            link_dirs: vec![link_dir.clone()],
+            regex: vec![Regex::new("^_").unwrap()],
+            cookie: Some("_".into()),
+            ..Rule::default()
+        }],
+        ..RawConfig::default()
+    };
+    raw_config.default_rule_settings.tag_on_link_drop = true;
+    let config = config_from_raw(&raw_config);
+
+    // expect a rule with nothing to tag with to be refused
+    let mut untaggable = raw_config.clone();
+    untaggable.rules[0].cookie = None;
+    assert!(Config::from_raw(untaggable).is_err());
+
+    let test_hook = {
+        clone_vars!(
+            tx,
+            file1,
+            file1_dropped,
+            file2,
+            file2_moved,
+            file3,
+            file3_dropped
+        );
+        move || {
+            create_symlinks!((file1, file1_dropped), (file3, file3_dropped));
+            rename_file(&file2, &file2_moved);
+
+            thread::sleep(Duration::from_millis(500));
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "link_dir",
+            "link_dir/_kick.wav",
+            "link_dir/_snare.wav",
+            "link_dir/my hat",
+            "watch_dir",
+            "watch_dir/_hat.wav",
+            "watch_dir/_kick.wav",
+            "watch_dir/_snare.wav",
+            "watch_dir/hat.wav",
+        ],
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn propagate_link_renames() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_propagate_link_renames").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+        (fav_dir = watch_dir / "fav"    : create = "dir"),
+        (fav_link_dir = root / "fav_link_dir" : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // its link will be renamed keeping the cookie, expect the source to follow
+        (_file1 = watch_dir / "_kick.wav" : create = "f"),
+        (file1_link = link_dir / "_kick.wav" : create = "no"),
+        (file1_link_renamed = link_dir / "_kick 808.wav" : create = "no"),
+        // its link will be renamed dropping the cookie, expect it to be re-applied
+        (_file2 = watch_dir / "_snare.wav" : create = "f"),
+        (file2_link = link_dir / "_snare.wav" : create = "no"),
+        (file2_link_renamed = link_dir / "snare 909.wav" : create = "no"),
+        // its link will be renamed to a name taken in the watch_dir (by a dir, which isn't
+        // linked), expect the conflict to be reported and the source left alone
+        (file3 = watch_dir / "_hat.wav" : create = "f"),
+        (_dir1 = watch_dir / "_ride.wav" : create = "dir"),
+        (file3_link = link_dir / "_hat.wav" : create = "no"),
+        (file3_link_renamed = link_dir / "_ride.wav" : create = "no"),
+        // matched on its relative path, which its new name keeps, expect no cookie
+        (_file4 = fav_dir / "clap.wav" : create = "f"),
+        (file4_link = fav_link_dir / "clap.wav" : create = "no"),
+        (file4_link_renamed = fav_link_dir / "clap 2.wav" : create = "no"),
+    );
+
+    // define config
+    let mut raw_config = RawConfig {
+        rules: vec![
+            Rule {
+                name: "test".into(),
+                watch_dirs: vec![watch_dir.clone()],
+                link_dirs: vec![link_dir.clone()],
+                regex: vec![Regex::new("^_").unwrap()],
+                cookie: Some("_".into()),
+                ..Rule::default()
+            },
+            Rule {
+                name: "fav".into(),
+                watch_dirs: vec![watch_dir.clone()],
+                link_dirs: vec![fav_link_dir.clone()],
+                regex: vec![Regex::new("^fav/").unwrap()],
+                cookie: Some("_".into()),
+                raw_settings: Some(RawRuleSettings {
+                    match_on: Some(MatchOn::RelativePath),
+                    ..RawRuleSettings::default()
+                }),
+                ..Rule::default()
+            },
+        ],
+        ..RawConfig::default()
+    };
+    raw_config.default_rule_settings.propagate_link_renames = true;
+    let config = config_from_raw(&raw_config);
+
+    let test_hook = {
+        clone_vars!(
+            tx,
+            file1_link,
+            file1_link_renamed,
+            file2_link,
+            file2_link_renamed,
+            file3_link,
+            file3_link_renamed,
+            file4_link,
+            file4_link_renamed
+        );
+        move || {
+            rename_file(&file1_link, &file1_link_renamed);
+            rename_file(&file2_link, &file2_link_renamed);
+            rename_file(&file3_link, &file3_link_renamed);
+            rename_file(&file4_link, &file4_link_renamed);
+
+            thread::sleep(Duration::from_millis(500));
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert!(file3.exists());
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "fav_link_dir",
+            "fav_link_dir/clap 2.wav",
+            "link_dir",
+            "link_dir/_kick 808.wav",
+            "link_dir/_ride.wav",
+            "link_dir/_snare 909.wav",
+            "watch_dir",
+            "watch_dir/_hat.wav",
+            "watch_dir/_kick 808.wav",
+            "watch_dir/_ride.wav",
+            "watch_dir/_snare 909.wav",
+            "watch_dir/fav",
+            "watch_dir/fav/clap 2.wav",
+        ],
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn remember_tags() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_remember_tags").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir1 = root / "watch_dir1" : create = "dir"),
+        (link_dir1 = root / "link_dir1"   : create = "dir"),
+        (watch_dir2 = root / "watch_dir2" : create = "dir"),
+        (link_dir2 = root / "link_dir2"   : create = "dir"),
+        (watch_dir3 = root / "watch_dir3" : create = "dir"),
+        (link_dir3 = root / "link_dir3"   : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // tagged, deleted and downloaded again, expect the copy to be tagged
+        (file1 = watch_dir1 / "_kick.wav" : create = "no"),
+        (file1_part = root / "kick.wav.part" : create = "no"),
+        (file1_again = watch_dir1 / "kick.wav" : create = "no"),
+        // tagged, deleted and copied back in, expect the copy to be tagged
+        (file2 = watch_dir1 / "_snare.wav" : create = "no"),
+        (file2_again = watch_dir1 / "snare (1).wav" : create = "no"),
+        // never tagged, expect it to be left alone
+        (file3 = watch_dir1 / "hat.wav" : create = "no"),
+        // tagged for the link policy, expect the copy to be linked as is, and kept linked
+        (file4 = watch_dir2 / "_clap.wav" : create = "no"),
+        (file4_again = watch_dir2 / "clap.wav" : create = "no"),
+        // tagged by xattr, then untagged in place, expect its link to be deleted
+        (file5 = watch_dir3 / "ride.wav" : create = "no"),
+        (index_path = root / "index.yml" : create = "no"),
+    );
+    fs::write(&file1, "kick").unwrap();
+    fs::write(&file2, "snare").unwrap();
+    fs::write(&file4, "clap").unwrap();
+    fs::write(&file5, "ride").unwrap();
+    xattr::set(&file5, "user.xdg.tags", b"fav").unwrap();
+
+    // define config
+    let mut raw_config = RawConfig {
+        rules: vec![
+            Rule {
+                name: "fav".into(),
+                watch_dirs: vec![watch_dir1.clone()],
+                link_dirs: vec![link_dir1.clone()],
+                regex: vec![Regex::new("^_").unwrap()],
+                cookie: Some("_".into()),
+                raw_settings: Some(RawRuleSettings {
+                    recall_policy: Some(RecallPolicy::Rename),
+                    ..RawRuleSettings::default()
+                }),
+                ..Rule::default()
+            },
+            Rule {
+                name: "claps".into(),
+                watch_dirs: vec![watch_dir2.clone()],
+                link_dirs: vec![link_dir2.clone()],
+                regex: vec![Regex::new("^_").unwrap()],
+                raw_settings: Some(RawRuleSettings {
+                    recall_policy: Some(RecallPolicy::Link),
+                    ..RawRuleSettings::default()
+                }),
+                ..Rule::default()
+            },
+            Rule {
+                name: "rides".into(),
+                watch_dirs: vec![watch_dir3.clone()],
+                link_dirs: vec![link_dir3.clone()],
+                xattr_tag: Some("fav".into()),
+                ..Rule::default()
+            },
+        ],
+        ..RawConfig::default()
+    };
+    raw_config.misc.index_path = index_path.clone();
+    raw_config.default_rule_settings.remember_tags = true;
+    let config = config_from_raw(&raw_config);
+
+    let test_hook = {
+        clone_vars!(
+            tx,
+            file1,
+            file1_part,
+            file1_again,
+            file2,
+            file2_again,
+            file3,
+            file4,
+            file4_again,
+            file5
+        );
+        move || {
+            fs::remove_file(&file1).unwrap();
+            fs::remove_file(&file2).unwrap();
+            fs::remove_file(&file4).unwrap();
+
+            thread::sleep(Duration::from_millis(300));
+            fs::write(&file1_part, "kick").unwrap();
+            rename_file(&file1_part, &file1_again);
+            fs::write(&file2_again, "snare").unwrap();
+            fs::write(&file3, "hat").unwrap();
+            fs::write(&file4_again, "clap").unwrap();
+            xattr::remove(&file5, "user.xdg.tags").unwrap();
+
+            // a cleanup must keep the recalled link
+            thread::sleep(Duration::from_millis(300));
+            send_request(&tx, Action::CleanAll).blocking_recv().unwrap();
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "index.yml",
+            "link_dir1",
+            "link_dir1/_kick.wav",
+            "link_dir1/_snare (1).wav",
+            "link_dir2",
+            "link_dir2/clap.wav",
+            "link_dir3",
+            "watch_dir1",
+            "watch_dir1/_kick.wav",
+            "watch_dir1/_snare (1).wav",
+            "watch_dir1/hat.wav",
+            "watch_dir2",
+            "watch_dir2/clap.wav",
+            "watch_dir3",
+            "watch_dir3/ride.wav",
+        ],
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dedupe() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_dedupe").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir1 = root / "watch_dir1" : create = "dir"),
+        (link_dir1 = root / "link_dir1"   : create = "dir"),
+        (watch_dir2 = root / "watch_dir2" : create = "dir"),
+        (link_dir2 = root / "link_dir2"   : create = "dir"),
+        (pack_dir = watch_dir1 / "pack"   : create = "dir"),
+        (sub_dir = watch_dir2 / "sub"     : create = "dir"),
+        (watch_dir3 = root / "watch_dir3" : create = "dir"),
+        (link_dir3 = root / "link_dir3"   : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // same content, expect only the shortest path to be linked
+        (file1 = pack_dir / "_kick.wav" : create = "no"),
+        (file2 = watch_dir1 / "_k.wav" : create = "no"),
+        // expect it to be replaced by a shorter duplicate moved in later
+        (file3 = watch_dir1 / "_snare.wav" : create = "no"),
+        (file4_tmp = root / "_s.wav" : create = "no"),
+        (file4 = watch_dir1 / "_s.wav" : create = "no"),
+        // same content, expect both to be linked and reported
+        (file5 = watch_dir2 / "_a.wav" : create = "no"),
+        (file6 = sub_dir / "_b.wav" : create = "no"),
+        // same content, both linked by an earlier run, expect the link to the copy scanned
+        // first to be kept, and the other to be deleted
+        (file7 = watch_dir3 / "_x.wav" : create = "no"),
+        (file8 = watch_dir3 / "_y.wav" : create = "no"),
+    );
+    fs::write(&file1, "kick").unwrap();
+    fs::write(&file2, "kick").unwrap();
+    fs::write(&file3, "snare").unwrap();
+    fs::write(&file5, "a").unwrap();
+    fs::write(&file6, "a").unwrap();
+    fs::write(&file7, "x").unwrap();
+    fs::write(&file8, "x").unwrap();
+    std::os::unix::fs::symlink(&file7, link_dir3.join("_x.wav")).unwrap();
+    std::os::unix::fs::symlink(&file8, link_dir3.join("_y.wav")).unwrap();
+
+    // define config
+    let raw_config = RawConfig {
+        rules: vec![
+            Rule {
+                name: "shortest".into(),
+                watch_dirs: vec![watch_dir1.clone()],
+                link_dirs: vec![link_dir1.clone()],
+                regex: vec![Regex::new("^_").unwrap()],
+                raw_settings: Some(RawRuleSettings {
+                    dedupe: Some(Some(Dedupe::KeepShortestPath)),
+                    ..RawRuleSettings::default()
+                }),
+                ..Rule::default()
+            },
+            Rule {
+                name: "report".into(),
+                watch_dirs: vec![watch_dir2.clone()],
+                link_dirs: vec![link_dir2.clone()],
+                regex: vec![Regex::new("^_").unwrap()],
+                raw_settings: Some(RawRuleSettings {
+                    dedupe: Some(Some(Dedupe::Report)),
+                    ..RawRuleSettings::default()
+                }),
+                ..Rule::default()
+            },
+            Rule {
+                name: "first".into(),
+                watch_dirs: vec![watch_dir3.clone()],
+                link_dirs: vec![link_dir3.clone()],
+                regex: vec![Regex::new("^_").unwrap()],
+                raw_settings: Some(RawRuleSettings {
+                    dedupe: Some(Some(Dedupe::KeepFirst)),
+                    ..RawRuleSettings::default()
+                }),
+                ..Rule::default()
+            },
+        ],
+        ..RawConfig::default()
+    };
+    let config = config_from_raw(&raw_config);
+
+    let test_hook = {
+        clone_vars!(tx, file4_tmp, file4);
+        move || {
+            fs::write(&file4_tmp, "snare").unwrap();
+            rename_file(&file4_tmp, &file4);
+
+            thread::sleep(Duration::from_millis(300));
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config.clone(), tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "link_dir1",
+            "link_dir1/_k.wav",
+            "link_dir1/_s.wav",
+            "link_dir2",
+            "link_dir2/_a.wav",
+            "link_dir2/_b.wav",
+            "link_dir3",
+            "link_dir3/_x.wav",
+            "watch_dir1",
+            "watch_dir1/_k.wav",
+            "watch_dir1/_s.wav",
+            "watch_dir1/_snare.wav",
+            "watch_dir1/pack",
+            "watch_dir1/pack/_kick.wav",
+            "watch_dir2",
+            "watch_dir2/_a.wav",
+            "watch_dir2/sub",
+            "watch_dir2/sub/_b.wav",
+            "watch_dir3",
+            "watch_dir3/_x.wav",
+            "watch_dir3/_y.wav",
+        ],
+    );
+
+    let mut hashes = HashCache::default();
+    assert!(find_duplicates(&config, 0, &mut hashes).unwrap().is_empty());
+    let groups = find_duplicates(&config, 1, &mut hashes).unwrap();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(
+        groups[0].links,
+        vec![
+            (link_dir2.join("_a.wav"), file5.clone()),
+            (link_dir2.join("_b.wav"), file6.clone()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn export_import() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_export_import").entered();
+
+    // create dirs
+    let_paths!(
+        (alice_dir = root / "alice"        : create = "dir"),
+        (alice_links = root / "alice_links" : create = "dir"),
+        (bob_dir = root / "bob"            : create = "dir"),
+        (bob_links = root / "bob_links"    : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // tagged by alice, expect bob's untagged copy to be tagged on import
+        (_file1 = alice_dir / "_kick.wav" : create = "f"),
+        (_file2 = bob_dir / "kick.wav" : create = "f"),
+        // tagged by alice, but bob doesn't have it, expect it to be reported
+        (_file3 = alice_dir / "_snare.wav" : create = "f"),
+        // never tagged, expect it to be left alone
+        (_file4 = alice_dir / "hat.wav" : create = "f"),
+        (_file5 = bob_dir / "hat.wav" : create = "f"),
+        // outside bob's watch_dir, expect records leading to it to be skipped
+        (outside = root / "outside.wav" : create = "f"),
+    );
+
+    // define configs
+    let rule = |watch_dir: &std::path::Path, link_dir: &std::path::Path| Rule {
+        name: "fav".into(),
+        watch_dirs: vec![watch_dir.to_path_buf()],
+        link_dirs: vec![link_dir.to_path_buf()],
+        regex: vec![Regex::new("^_").unwrap()],
+        cookie: Some("_".into()),
+        ..Rule::default()
+    };
+    let alice_config = config_from_raw(&RawConfig {
+        rules: vec![rule(&alice_dir, &alice_links)],
+        ..RawConfig::default()
+    });
+    let bob_config = config_from_raw(&RawConfig {
+        rules: vec![rule(&bob_dir, &bob_links)],
+        ..RawConfig::default()
+    });
+
+    // link alice's files
+    let test_hook = {
+        clone_vars!(tx);
+        move || send_shutdown(&tx)
+    };
+    run_with_config(alice_config.clone(), tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // export, through CSV
+    let records = collect_membership(&alice_config).unwrap();
+    assert_eq!(records.len(), 2);
+    let mut csv = vec![];
+    write_membership(&records, ExportFormat::Csv, &mut csv).unwrap();
+    let read_records = read_membership(csv.as_slice(), ExportFormat::Csv).unwrap();
+    assert_eq!(read_records, records);
+
+    // import on bob's side
+    let remaps = vec![format!("{}={}", alice_dir.display(), bob_dir.display())
+        .parse::<PathRemap>()
+        .unwrap()];
+    let report = import_membership(&bob_config, &read_records, &remaps).unwrap();
+    assert_eq!(report.tagged, vec![bob_dir.join("_kick.wav")]);
+    assert_eq!(report.not_found, vec![bob_dir.join("_snare.wav")]);
+    assert!(report.already_tagged.is_empty());
+    assert!(report.skipped.is_empty());
+
+    // expect records with absolute paths, or paths leading out of the watch_dir, to be skipped
+    let escaping = ["../_outside.wav".into(), outside.clone()].map(|path| Membership {
+        path,
+        ..read_records[0].clone()
+    });
+    let report = import_membership(&bob_config, &escaping, &remaps).unwrap();
+    assert_eq!(report.skipped.len(), 2);
+    assert!(report.tagged.is_empty() && report.not_found.is_empty());
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "alice",
+            "alice/_kick.wav",
+            "alice/_snare.wav",
+            "alice/hat.wav",
+            "alice_links",
+            "alice_links/_kick.wav",
+            "alice_links/_snare.wav",
+            "bob",
+            "bob/_kick.wav",
+            "bob/hat.wav",
+            "bob_links",
+            "outside.wav",
+        ],
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn playlists() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_playlists").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir"     : create = "dir"),
+        (sub_dir = watch_dir / "sub"        : create = "dir"),
+        (playlist_dir = root / "playlists"  : create = "no"),
+    );
+
+    // create files
+    let_paths!(
+        // expect it to be listed
+        (_file1 = sub_dir / "_b.wav" : create = "f"),
+        // expect it to be removed from the playlists once deleted
+        (file2 = watch_dir / "_c & d.wav" : create = "f"),
+        // expect it to be added once tagged
+        (file3 = watch_dir / "a.wav" : create = "f"),
+        (file3_tagged = watch_dir / "_a.wav" : create = "no"),
+        (m3u8_path = playlist_dir / "fav.m3u8" : create = "no"),
+        (xspf_path = playlist_dir / "fav.xspf" : create = "no"),
+    );
+
+    // define config, with playlists instead of link_dirs
+    let raw_config = RawConfig {
+        rules: vec![Rule {
+            name: "fav".into(),
+            watch_dirs: vec![watch_dir.clone()],
+            playlists: vec![
+                Playlist {
+                    path: m3u8_path.clone(),
+                    relative_paths: true,
+                    sort: PlaylistSort::Name,
+                    ..Playlist::default()
+                },
+                Playlist {
+                    path: xspf_path.clone(),
+                    ..Playlist::default()
+                },
+            ],
+            regex: vec![Regex::new("^_").unwrap()],
+            ..Rule::default()
+        }],
+        ..RawConfig::default()
+    };
+    let config = config_from_raw(&raw_config);
+
+    // a playlist needs somewhere to be written, that doesn't depend on the working directory
+    for path in ["", "fav.m3u8"] {
+        let mut bad_path_config = raw_config.clone();
+        bad_path_config.rules[0].playlists[1].path = PathBuf::from(path);
+        assert!(matches!(
+            Config::from_raw(bad_path_config),
+            Err(Error::Config(_))
+        ));
+    }
+    assert!(serde_yml::from_str::<Playlist>("sort: name").is_err());
+
+    let test_hook = {
+        clone_vars!(tx, file2, file3, file3_tagged, m3u8_path);
+        move || {
+            assert_eq!(
+                fs::read_to_string(&m3u8_path).unwrap(),
+                "#EXTM3U\n../watch_dir/sub/_b.wav\n../watch_dir/_c & d.wav\n"
+            );
+            fs::remove_file(&file2).unwrap();
+            rename_file(&file3, &file3_tagged);
+
+            thread::sleep(Duration::from_millis(300));
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_eq!(
+        fs::read_to_string(&m3u8_path).unwrap(),
+        "#EXTM3U\n../watch_dir/_a.wav\n../watch_dir/sub/_b.wav\n"
+    );
+    let xspf = fs::read_to_string(&xspf_path).unwrap();
+    let locations = xspf
+        .lines()
+        .filter_map(|line| line.split("<location>").nth(1))
+        .filter_map(|rest| rest.split("</location>").next())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        locations,
+        vec![
+            format!("file://{}", file3_tagged.display()),
+            format!("file://{}", sub_dir.join("_b.wav").display()),
+        ]
+    );
+    assert!(xspf.contains("<title>fav</title>"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hooks() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_hooks").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (sub_dir = watch_dir / "sub"    : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+        (slow_dir = root / "slow_dir"   : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // expect both to be linked, one as "0__a.wav" after a conflict
+        (_file1 = watch_dir / "_a.wav" : create = "f"),
+        (_file2 = sub_dir / "_a.wav"   : create = "f"),
+        // expect its link to be unlinked once it's deleted
+        (file3 = watch_dir / "_c.wav"  : create = "f"),
+        (log_path = root / "hooks.log" : create = "no"),
+    );
+
+    // define config, with a second rule whose hook always times out
+    let log_line = r#"echo "$FILETAGS_EVENT $FILETAGS_RULE $(basename "$FILETAGS_SOURCE") $(basename "${FILETAGS_LINK:-none}")""#;
+    let raw_config = RawConfig {
+        misc: MiscSettings {
+            hook_timeout: Age(Duration::from_millis(200)),
+            ..MiscSettings::default()
+        },
+        rules: vec![
+            Rule {
+                name: "fav".into(),
+                watch_dirs: vec![watch_dir.clone()],
+                link_dirs: vec![link_dir.clone()],
+                regex: vec![Regex::new("^_").unwrap()],
+                on_link: Some(format!("{} >> {:?}", log_line, log_path)),
+                on_unlink: Some(format!("{} >> {:?}", log_line, log_path)),
+                on_conflict: Some(format!("{} >> {:?}", log_line, log_path)),
+                ..Rule::default()
+            },
+            Rule {
+                name: "slow".into(),
+                watch_dirs: vec![watch_dir.clone()],
+                link_dirs: vec![slow_dir.clone()],
+                regex: vec![Regex::new("^_c").unwrap()],
+                // in a subshell, which outlives the shell unless its whole group is killed
+                on_link: Some(format!("(sleep 0.4; echo late >> {:?}); true", log_path)),
+                ..Rule::default()
+            },
+        ],
+        ..RawConfig::default()
+    };
+    let config = config_from_raw(&raw_config);
+
+    let test_hook = {
+        clone_vars!(tx, file3);
+        move || {
+            fs::remove_file(&file3).unwrap();
+
+            // long enough for the slow hook to have finished, had it not been killed
+            thread::sleep(Duration::from_millis(600));
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    let log = fs::read_to_string(&log_path).unwrap();
+    let mut lines = log.lines().collect::<Vec<_>>();
+    lines.sort();
+    assert_eq!(
+        lines,
+        vec![
+            "conflict fav _a.wav 0__a.wav",
+            "link fav _a.wav 0__a.wav",
+            "link fav _a.wav _a.wav",
+            "link fav _c.wav _c.wav",
+            "unlink fav _c.wav _c.wav",
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn events() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_events").entered();
+
+    // create dirs
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+    );
+
+    // create files
+    let_paths!(
+        // expect it to be linked at startup, then unlinked once it's deleted
+        (file1 = watch_dir / "_a.wav"       : create = "f"),
+        (link1 = link_dir / "_a.wav"        : create = "no"),
+        // expect it to be linked once it's created
+        (file2 = watch_dir / "_b.wav"       : create = "no"),
+        (link2 = link_dir / "_b.wav"        : create = "no"),
+        (socket_path = root / "events.sock" : create = "no"),
+    );
+
+    // define config
+    let raw_config = RawConfig {
+        misc: MiscSettings {
+            event_socket: Some(socket_path.clone()),
+            ..MiscSettings::default()
+        },
+        rules: vec![Rule {
+            name: "fav".into(),
+            watch_dirs: vec![watch_dir.clone()],
+            link_dirs: vec![link_dir.clone()],
+            regex: vec![Regex::new("^_").unwrap()],
+            ..Rule::default()
+        }],
+        ..RawConfig::default()
+    };
+    let config = config_from_raw(&raw_config);
+
+    // follow the events served on the socket, until none come for a while
+    let (lines_tx, lines_rx) = std::sync::mpsc::channel::<String>();
+    let test_hook = {
+        clone_vars!(tx, file1, file2, socket_path);
+        move || {
+            let mut stream = UnixStream::connect(&socket_path).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_millis(1000)))
+                .unwrap();
+            writeln!(stream, "follow").unwrap();
+            let lines_tx = lines_tx.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stream).lines() {
+                    let Ok(line) = line else { break };
+                    lines_tx.send(line).unwrap();
+                }
+            });
+
+            thread::sleep(Duration::from_millis(300));
+            create_files!(file2);
+            thread::sleep(Duration::from_millis(100));
+            fs::remove_file(&file1).unwrap();
+
+            thread::sleep(Duration::from_millis(300));
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    let received = lines_rx
+        .iter()
+        .map(|line| serde_json::from_str::<Event>(&line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        received,
+        vec![
+            Event::ScanStarted,
+            Event::link_created("fav", &file1, &link1),
+            Event::ScanFinished { links: 1 },
+            Event::link_created("fav", &file2, &link2),
+            Event::link_removed("fav", &file1, &link1),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn dispatcher_subscribe() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_dispatcher_subscribe").entered();
+
+    // create dirs and files
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+        (file1 = watch_dir / "_a.wav"   : create = "f"),
+        (link1 = link_dir / "_a.wav"    : create = "no"),
+        // a broken link, expected to be retargeted to file1
+        (old_file = root / "gone.wav"   : create = "no"),
+        (_old_link = link_dir / "_a.wav" : create = "symlink" -> old_file),
+    );
+    let config = create_config!(("fav", (watch_dir), (link_dir), "^_"));
+
+    // embed the dispatcher, without watchers
+    let dispatcher = Dispatcher::new(rx, tx.clone(), config).unwrap();
+    let mut events = dispatcher.subscribe();
+    let dispatcher = dispatcher.run(Action::SymlinkAll).unwrap();
+    send_shutdown(&tx);
+    dispatcher.rx_handle.await.unwrap().unwrap();
+
+    // assertions
+    let mut received = vec![];
+    while let Ok(event) = events.try_recv() {
+        received.push(event);
+    }
+    assert_eq!(
+        received,
+        vec![
+            Event::ScanStarted,
+            Event::link_retargeted("fav", &old_file, &file1, &link1),
+            Event::ScanFinished { links: 1 },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn config_builder() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_config_builder").entered();
+
+    // create dirs and files
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+        (_file1 = watch_dir / "_a.wav"  : create = "f"),
+        // a regular file where the link to file1 should go
+        (link1 = link_dir / "_a.wav"    : create = "f"),
+    );
+
+    // expect the builder to merge settings the same way a config file does
+    let default_settings = RuleSettings {
+        clean_interval: None,
+        ..RuleSettings::default()
+    };
+    let rule_settings = RawRuleSettings {
+        targets: Some(Targets::Both),
+        ..RawRuleSettings::default()
+    };
+    let built = Config::builder()
+        .default_settings(default_settings.clone())
+        .rule(
+            Rule::builder("fav")
+                .watch_dir(&watch_dir)
+                .link_dir(&link_dir)
+                .regex("^_")
+                .extension("wav")
+                .settings(rule_settings.clone()),
+        )
+        .rule(Rule::builder("fav_query").query("fav"))
+        .build()
+        .unwrap();
+    let loaded = config_from_raw(&RawConfig {
+        rules: vec![
+            Rule {
+                name: "fav".into(),
+                watch_dirs: vec![watch_dir.clone()],
+                link_dirs: vec![link_dir.clone()],
+                regex: vec![Regex::new("^_").unwrap()],
+                extensions: vec!["wav".into()],
+                raw_settings: Some(rule_settings),
+                ..Rule::default()
+            },
+            Rule {
+                name: "fav_query".into(),
+                query: Some("fav".parse().unwrap()),
+                ..Rule::default()
+            },
+        ],
+        default_rule_settings: default_settings,
+        ..RawConfig::default()
+    });
+    assert_eq!(
+        serde_yml::to_string(&*built).unwrap(),
+        serde_yml::to_string(&*loaded).unwrap()
+    );
+    assert_eq!(built.rules[0].settings.targets, Targets::Both);
+    assert_eq!(built.rules[0].settings.clean_interval, None);
+    assert_eq!(built.rules[1].watch_dirs, vec![watch_dir.clone()]);
+
+    // expect failures to be matchable
+    assert!(matches!(
+        Config::builder()
+            .rule(Rule::builder("fav").regex("(unclosed"))
+            .build(),
+        Err(Error::Regex(_))
+    ));
+    assert!(matches!(
+        Config::builder()
+            .rule(Rule::builder("fav").regex("^_"))
+            .rule(Rule::builder("fav").regex("^-"))
+            .build(),
+        Err(Error::Config(_))
+    ));
+    let config = Config::builder()
+        .rule(
+            Rule::builder("fav")
+                .watch_dir(&watch_dir)
+                .link_dir(&link_dir)
+                .regex("^_"),
+        )
+        .build()
+        .unwrap();
+    match run_with_config(config, tx, rx, None::<fn()>).await {
+        Err(Error::Conflict(path)) => assert_eq!(path, link1),
+        other => panic!("expected a conflict, got {:?}", other),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn action_reports() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_action_reports").entered();
+
+    // create dirs and files
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+        // same content, expect only one to be linked
+        (file1 = watch_dir / "_a.wav"   : create = "no"),
+        (file2 = watch_dir / "_b.wav"   : create = "no"),
+        // broken symlink, expect it to be removed
+        (gone = root / "gone.wav"             : create = "no"),
+        (broken_link = link_dir / "_gone.wav" : create = "symlink" -> gone),
+    );
+    fs::write(&file1, "same").unwrap();
+    fs::write(&file2, "same").unwrap();
+    let raw_config = RawConfig {
+        rules: vec![Rule {
+            name: "fav".into(),
+            watch_dirs: vec![watch_dir.clone()],
+            link_dirs: vec![link_dir.clone()],
+            regex: vec![Regex::new("^_").unwrap()],
+            raw_settings: Some(RawRuleSettings {
+                dedupe: Some(Some(Dedupe::KeepFirst)),
+                ..RawRuleSettings::default()
+            }),
+            ..Rule::default()
+        }],
+        ..RawConfig::default()
+    };
+    let config = config_from_raw(&raw_config);
+
+    // expect each action to report what it did
+    let dispatcher = Dispatcher::new(rx, tx.clone(), Arc::clone(&config)).unwrap();
+    let report = dispatcher.perform(Action::CleanAll).await.unwrap();
+    assert_eq!(report.removed, vec![broken_link.clone()]);
+    assert!(report.created.is_empty() && report.errors.is_empty());
+    let report = dispatcher.perform(Action::SymlinkAll).await.unwrap();
+    assert_eq!(report.created.len(), 1);
+    assert_eq!(report.skipped.len(), 1);
+    assert!(report.errors.is_empty());
+
+    // expect a failed action to be reported, without stopping the responder
+    let_paths!(
+        (file3 = watch_dir / "_c.wav" : create = "f"),
+        (link3 = link_dir / "_c.wav"  : create = "f"),
+    );
+    match dispatcher
+        .perform(Action::SymlinkAll)
+        .await
+        .unwrap()
+        .into_result()
+    {
+        Err(Error::Conflict(path)) => assert_eq!(path, link3),
+        other => panic!("expected a conflict, got {:?}", other),
+    }
+    fs::remove_file(&link3).unwrap();
+    let report = dispatcher.perform(Action::SymlinkAll).await.unwrap();
+    assert_eq!(report.created, vec![link3.clone()]);
+    send_shutdown(&tx);
+    dispatcher.rx_handle.await.unwrap().unwrap();
+
+    // expect requests from the test hook to be awaitable too
+    let (tx, rx) = create_tx_rx!();
+    let test_hook = {
+        clone_vars!(tx, broken_link);
+        move || {
+            // link_dirs aren't watched, so only the request removes it
+            std::os::unix::fs::symlink(&gone, &broken_link).unwrap();
+            let report = send_request(&tx, Action::CleanAll).blocking_recv().unwrap();
+            assert_eq!(report.removed, vec![broken_link.clone()]);
+            send_shutdown(&tx);
+        }
+    };
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+}
+
+/// A matcher standing in for a database lookup.
+#[derive(Debug)]
+struct ListedMatcher(Vec<PathBuf>);
+
+impl Matcher for ListedMatcher {
+    fn matches(&self, input: &MatchInput) -> filetags::Result<Option<Match>> {
+        Ok(self.0.iter().any(|p| p == input.path).then(Match::default))
+    }
+}
+
+#[tokio::test]
+async fn custom_matchers() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_custom_matchers").entered();
+
+    // create dirs and files
+    let_paths!(
+        (watch_dir = root / "watch_dir" : create = "dir"),
+        (link_dir = root / "link_dir"   : create = "dir"),
+        // listed, expect it to be linked
+        (file1 = watch_dir / "kick.wav" : create = "f"),
+        (link1 = link_dir / "kick.wav"  : create = "no"),
+        // not listed, expect its link to be cleaned
+        (file2 = watch_dir / "snare.wav" : create = "f"),
+        (_link2 = link_dir / "snare.wav" : create = "symlink" -> file2),
+    );
+
+    // expect the regex matcher to capture groups, by index and by name
+    let regex = vec![Regex::new(r"^_(?<name>\w+)\.wav$").unwrap()];
+    let found = regex
+        .matches(&MatchInput::new(
+            &watch_dir.join("_hat.wav"),
+            &watch_dir,
+            MatchOn::Basename,
+        ))
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.captures["1"], "hat");
+    assert_eq!(found.captures["name"], "hat");
+    assert!(regex
+        .matches(&MatchInput::new(&file1, &watch_dir, MatchOn::Basename))
+        .unwrap()
+        .is_none());
+
+    // expect unregistered matchers to be rejected
+    assert!(matches!(
+        Config::builder()
+            .rule(Rule::builder("db").matcher("missing"))
+            .build(),
+        Err(Error::Config(_))
+    ));
+
+    let config = Config::builder()
+        .matcher("db", ListedMatcher(vec![file1.clone()]))
+        .rule(
+            Rule::builder("db")
+                .watch_dir(&watch_dir)
+                .link_dir(&link_dir)
+                .matcher("db"),
+        )
+        .build()
+        .unwrap();
+    let test_hook = {
+        clone_vars!(tx);
+        move || send_shutdown(&tx)
+    };
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "link_dir",
+            "link_dir/kick.wav",
+            "watch_dir",
+            "watch_dir/kick.wav",
+            "watch_dir/snare.wav",
+        ],
+    );
+    assert_eq!(fs::read_link(&link1).unwrap(), file1);
+}
+
+/// A matcher standing in for a database lookup, capturing the artist it knows of.
+#[cfg(feature = "scripting")]
+#[derive(Debug)]
+struct ArtistMatcher(PathBuf, &'static str);
+
+#[cfg(feature = "scripting")]
+impl Matcher for ArtistMatcher {
+    fn matches(&self, input: &MatchInput) -> filetags::Result<Option<Match>> {
+        Ok((input.path == self.0).then(|| Match {
+            captures: [("artist".to_string(), self.1.to_string())].into(),
+        }))
+    }
+}
+
+#[cfg(feature = "scripting")]
+#[tokio::test]
+async fn scripts() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_scripts").entered();
+
+    // create dirs and files
+    let_paths!(
+        (watch_dir = root / "watch_dir"  : create = "dir"),
+        (bpm_dir = root / "bpm_dir"      : create = "dir"),
+        (recent_dir = root / "recent_dir" : create = "dir"),
+        // expect only the loop within the BPM range to be linked
+        (_file1 = watch_dir / "_loop 120bpm.wav" : create = "f"),
+        (_file2 = watch_dir / "_loop 130bpm.wav" : create = "f"),
+        // no BPM to parse, expect the script to fail and reject it
+        (_file3 = watch_dir / "_loop.wav"        : create = "f"),
+        // expect only the recent note to be linked, under the name chosen by the script
+        (file4 = watch_dir / "2026-10-15 notes.txt" : create = "f"),
+        (_file5 = watch_dir / "2019-01-01 notes.txt" : create = "f"),
+        // linked under the name an earlier version of the script chose, expect it deleted
+        (_file4_old_link = recent_dir / "2026-10-15 notes.txt" : create = "symlink" -> file4),
+        // matched by a matcher, expect it to be linked under the name made from its captures
+        (artist_dir = root / "artist_dir" : create = "dir"),
+        (file6 = watch_dir / "kick.wav"   : create = "f"),
+    );
+    let config = Config::builder()
+        .matcher("artists", ArtistMatcher(file6.clone(), "alice"))
+        .rule(
+            Rule::builder("artist")
+                .watch_dir(&watch_dir)
+                .link_dir(&artist_dir)
+                .matcher("artists")
+                .script(r#"captures.artist + " - " + name"#),
+        )
+        .rule(
+            Rule::builder("bpm")
+                .watch_dir(&watch_dir)
+                .link_dir(&bpm_dir)
+                .regex(r"^_(?:.*?(?<bpm>\d+)bpm)?")
+                .script("let bpm = parse_int(captures.bpm); bpm >= 118 && bpm <= 126"),
+        )
+        .rule(
+            Rule::builder("recent")
+                .watch_dir(&watch_dir)
+                .link_dir(&recent_dir)
+                .script(
+                    r#"
+                    if is_dir || !name.starts_with("20") { return false; }
+                    let date = timestamp(
+                        parse_int(name.sub_string(0, 4)),
+                        parse_int(name.sub_string(5, 2)),
+                        parse_int(name.sub_string(8, 2)),
+                    );
+                    if date >= timestamp(2026, 1, 1) { "recent " + name } else { false }
+                    "#,
+                ),
+        )
+        .build()
+        .unwrap();
+
+    // expect scripts to be compiled when the config is built or loaded
+    assert!(matches!(
+        Config::builder()
+            .rule(Rule::builder("broken").script("let x = ;"))
+            .build(),
+        Err(Error::Config(_))
+    ));
+    assert!(serde_yml::from_str::<Config>(
+        "rules: [{name: broken, regex: ['^_'], script: 'parse_int(name'}]"
+    )
+    .is_err());
+
+    let test_hook = {
+        clone_vars!(tx);
+        move || send_shutdown(&tx)
+    };
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "artist_dir",
+            "artist_dir/alice - kick.wav",
+            "bpm_dir",
+            "bpm_dir/_loop 120bpm.wav",
+            "recent_dir",
+            "recent_dir/recent 2026-10-15 notes.txt",
+            "watch_dir",
+            "watch_dir/2019-01-01 notes.txt",
+            "watch_dir/2026-10-15 notes.txt",
+            "watch_dir/_loop 120bpm.wav",
+            "watch_dir/_loop 130bpm.wav",
+            "watch_dir/_loop.wav",
+            "watch_dir/kick.wav",
+        ],
+    );
+}
+
+#[test]
+fn config_formats() {
+    // init
+    let (_, root) = create_test_env();
+    let _logger = Logger::new();
+    let _span = info_span!("T_config_formats").entered();
+
+    // the same config, in every format
+    let_paths!(
+        (config_dir = root / "configs"          : create = "dir"),
+        (yaml_path = config_dir / "config.yml"  : create = "no"),
+        (toml_path = config_dir / "config.toml" : create = "no"),
+        (json_path = config_dir / "config.json" : create = "no"),
+        (converted_path = config_dir / "converted.toml" : create = "no"),
+        (untyped_path = config_dir / "config"   : create = "no"),
+    );
+    fs::write(
+        &yaml_path,
+        r#"
+misc:
+  hook_timeout: 5s
+default_settings:
+  targets: both
+rules:
+  - name: fav
+    watch_dirs: [~/Samples]
+    link_dirs: [~/Favorites]
+    regex: ['^_']
+    min_size: 1KB
+    settings:
+      clean_interval: 60
+"#,
+    )
+    .unwrap();
+    fs::write(
+        &toml_path,
+        r#"
+[misc]
+hook_timeout = "5s"
+
+[default_settings]
+targets = "both"
+
+[[rules]]
+name = "fav"
+watch_dirs = ["~/Samples"]
+link_dirs = ["~/Favorites"]
+regex = ["^_"]
+min_size = "1KB"
+
+[rules.settings]
+clean_interval = 60
+"#,
+    )
+    .unwrap();
+    fs::write(
+        &json_path,
+        r#"{"misc": {"hook_timeout": "5s"},
+            "default_settings": {"targets": "both"},
+            "rules": [{"name": "fav", "watch_dirs": ["~/Samples"],
+                       "link_dirs": ["~/Favorites"], "regex": ["^_"], "min_size": "1KB",
+                       "settings": {"clean_interval": 60}}]}"#,
+    )
+    .unwrap();
+
+    // expect the format to be picked by extension, with the same merging and path expansion
+    let load = |path: &PathBuf, format: Option<ConfigFormat>| {
+        let args = Args {
+            config_path: path.clone(),
+            config_format: format,
+            ..Args::default()
+        };
+        serde_yml::to_string(&*Config::create(&args).unwrap()).unwrap()
+    };
+    let from_yaml = load(&yaml_path, None);
+    assert_eq!(load(&toml_path, None), from_yaml);
+    assert_eq!(load(&json_path, None), from_yaml);
+    assert!(!from_yaml.contains('~'));
+
+    // expect --format to override the extension
+    fs::copy(&toml_path, &untyped_path).unwrap();
+    assert!(matches!(
+        Config::create(&Args {
+            config_path: untyped_path.clone(),
+            ..Args::default()
+        }),
+        Err(Error::Config(_))
+    ));
+    assert_eq!(load(&untyped_path, Some(ConfigFormat::Toml)), from_yaml);
+
+    // expect conversion to keep the config as written
+    run_config_command(
+        &ConfigCommand::Convert {
+            input: yaml_path.clone(),
+            output: Some(converted_path.clone()),
+            to: None,
+        },
+        None,
+    )
+    .unwrap();
+    let converted = fs::read_to_string(&converted_path).unwrap();
+    assert!(converted.contains("\"~/Samples\""));
+    assert_eq!(load(&converted_path, None), from_yaml);
+
+    // expect --format to override the extension of the input too
+    run_config_command(
+        &ConfigCommand::Convert {
+            input: untyped_path.clone(),
+            output: Some(converted_path.clone()),
+            to: None,
+        },
+        Some(ConfigFormat::Toml),
+    )
+    .unwrap();
+    assert_eq!(load(&converted_path, None), from_yaml);
+
+    // expect nulls to be refused by TOML, and invalid configs not to be converted
+    fs::write(
+        &json_path,
+        r#"{"rules": [{"name": "fav", "cookie": null}]}"#,
+    )
+    .unwrap();
+    assert!(run_config_command(
+        &ConfigCommand::Convert {
+            input: json_path.clone(),
+            output: None,
+            to: Some(ConfigFormat::Toml),
+        },
+        None
+    )
+    .is_err());
+    fs::write(&json_path, r#"{"rules": [{"name": "fav", "unknown": 1}]}"#).unwrap();
+    assert!(run_config_command(
+        &ConfigCommand::Convert {
+            input: json_path.clone(),
+            output: None,
+            to: Some(ConfigFormat::Yaml),
+        },
+        None
+    )
+    .is_err());
+}
+
+#[test]
+fn config_includes() {
+    // init
+    let (_, root) = create_test_env();
+    let _logger = Logger::new();
+    let _span = info_span!("T_config_includes").entered();
+
+    // a main config including a project's rules by glob, which include more in turn, and
+    // opting into its conf.d directory
+    let_paths!(
+        (config_dir = root / "configs"                   : create = "dir"),
+        (projects_dir = config_dir / "projects"          : create = "dir"),
+        (conf_d = config_dir / "conf.d"                  : create = "dir"),
+        (main_path = config_dir / "config.yml"           : create = "no"),
+        (project_path = projects_dir / "music.yml"       : create = "no"),
+        (nested_path = projects_dir / "music-extra.toml" : create = "no"),
+        (conf_d_path = conf_d / "photos.json"            : create = "no"),
+    );
+    fs::write(
+        &main_path,
+        r#"
+misc:
+  hook_timeout: 5s
+  conf_d: true
+default_settings:
+  targets: both
+include: ['projects/m*.yml']
+rules:
+  - name: fav
+    watch_dirs: [/tmp]
+    link_dirs: [/tmp]
+    regex: ['^_']
+"#,
+    )
+    .unwrap();
+    fs::write(
+        &project_path,
+        r#"
+default_settings:
+  clean_interval: 60
+include: [music-extra.toml]
+rules:
+  - name: music
+    watch_dirs: [/tmp]
+    link_dirs: [/tmp]
+    regex: ['^m_']
+    settings:
+      max_depth: 2
+"#,
+    )
+    .unwrap();
+    fs::write(
+        &nested_path,
+        r#"
+[default_settings]
+max_depth = 1
+
+[[rules]]
+name = "music-extra"
+watch_dirs = ["/tmp"]
+link_dirs = ["/tmp"]
+regex = ["^e_"]
+"#,
+    )
+    .unwrap();
+    fs::write(
+        &conf_d_path,
+        r#"{"rules": [{"name": "photos", "watch_dirs": ["/tmp"], "link_dirs": ["/tmp"],
+                       "regex": ["^p_"]}]}"#,
+    )
+    .unwrap();
+
+    // expect the rules in load order, with each file's defaults layered for its own rules
+    let config = Config::load(&main_path, None).unwrap();
+    let names = config
+        .rules
+        .iter()
+        .map(|r| r.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["fav", "music", "music-extra", "photos"]);
+    assert_eq!(
+        config.sources,
+        [
+            main_path.clone(),
+            project_path.clone(),
+            nested_path.clone(),
+            conf_d_path.clone()
+        ]
+    );
+    let settings = |name: &str| &config.rules[config.rule_idx(name).unwrap()].settings;
+    assert!(config
+        .rules
+        .iter()
+        .all(|r| r.settings.targets == Targets::Both));
+    assert_eq!(
+        settings("fav").clean_interval,
+        RuleSettings::default().clean_interval
+    );
+    assert_eq!(settings("music").clean_interval, Some(60));
+    assert_eq!(settings("music").max_depth, 2);
+    assert_eq!(settings("music-extra").clean_interval, Some(60));
+    assert_eq!(settings("music-extra").max_depth, 1);
+    assert_eq!(
+        settings("photos").clean_interval,
+        RuleSettings::default().clean_interval
+    );
+
+    // expect a duplicate rule name to point to both files
+    fs::write(
+        &conf_d_path,
+        r#"{"rules": [{"name": "music", "watch_dirs": ["/tmp"], "link_dirs": ["/tmp"],
+                       "regex": ["^p_"]}]}"#,
+    )
+    .unwrap();
+    let Err(Error::Config(message)) = Config::load(&main_path, None) else {
+        panic!("expected a duplicate rule name to be refused");
+    };
+    assert!(message.starts_with(&conf_d_path.display().to_string()));
+    assert!(message.contains(&format!("{:?}", project_path)));
+
+    // expect errors to point to the file they're in
+    fs::write(&conf_d_path, r#"{"misc": {"hook_timeout": "5s"}}"#).unwrap();
+    let Err(Error::Config(message)) = Config::load(&main_path, None) else {
+        panic!("expected misc to be refused outside the main config");
+    };
+    assert!(message.starts_with(&conf_d_path.display().to_string()));
+
+    // expect conf.d to be left alone outside the default config location, unless the main
+    // config opts into it
+    let main = fs::read_to_string(&main_path).unwrap();
+    fs::write(&main_path, main.replace("  conf_d: true\n", "")).unwrap();
+    let config = Config::load(&main_path, None).unwrap();
+    assert!(!config.sources.contains(&conf_d_path));
+    fs::remove_file(&conf_d_path).unwrap();
+    fs::write(
+        &nested_path,
+        "[[rules]]\nname = \"music-extra\"\ncookie = 1\n",
+    )
+    .unwrap();
+    let Err(Error::Config(message)) = Config::load(&main_path, None) else {
+        panic!("expected an unknown field to be refused");
+    };
+    assert!(message.starts_with(&nested_path.display().to_string()));
+
+    // expect a missing file, or a file included twice, to be refused
+    fs::write(&nested_path, "include = [\"music.yml\"]\n").unwrap();
+    assert!(Config::load(&main_path, None).is_err());
+    fs::remove_file(&nested_path).unwrap();
+    assert!(Config::load(&main_path, None).is_err());
+}
+
+#[test]
+fn rule_templates() {
+    // init
+    let _logger = Logger::new();
+    let _span = info_span!("T_rule_templates").entered();
+
+    // templates extending each other, and rules extending several templates
+    let config: Config = serde_yml::from_str(
+        r#"
+default_settings:
+  max_depth: 5
+templates:
+  - name: samples
+    watch_dirs: [/tmp/Samples]
+    link_dirs: [/tmp/Favorites]
+    settings:
+      targets: both
+      clean_interval: 60
+  - name: audio
+    extends: [samples]
+    extensions: [wav, flac]
+    settings:
+      targets: files
+rules:
+  - name: fav
+    extends: [audio]
+    regex: ['^_']
+  - name: loops
+    extends: [samples, audio]
+    link_dirs: [/tmp/Loops]
+    regex: ['loop']
+    settings:
+      clean_interval: 5
+  - name: drums
+    extends: [audio, samples]
+    regex: ['drum']
+"#,
+    )
+    .unwrap();
+
+    // expect each field to come from the rule, else its last template, else the defaults
+    let rule = |name: &str| &config.rules[config.rule_idx(name).unwrap()];
+    assert_eq!(rule("fav").watch_dirs, [PathBuf::from("/tmp/Samples")]);
+    assert_eq!(rule("fav").link_dirs, [PathBuf::from("/tmp/Favorites")]);
+    assert_eq!(rule("fav").extensions, ["wav", "flac"]);
+    assert_eq!(rule("fav").settings.targets, Targets::Files);
+    assert_eq!(rule("fav").settings.clean_interval, Some(60));
+    assert_eq!(rule("fav").settings.max_depth, 5);
+    assert_eq!(rule("loops").link_dirs, [PathBuf::from("/tmp/Loops")]);
+    assert_eq!(rule("loops").settings.targets, Targets::Files);
+    assert_eq!(rule("loops").settings.clean_interval, Some(5));
+    assert_eq!(rule("drums").extensions, ["wav", "flac"]);
+    assert_eq!(rule("drums").settings.targets, Targets::Both);
+    assert!(config.rule_idx("samples").is_none());
+
+    // expect unknown templates and cycles to be refused
+    let parse = |yaml: &str| serde_yml::from_str::<Config>(yaml);
+    assert!(
+        parse("rules: [{name: fav, extends: [nope], regex: ['^_'], watch_dirs: [/tmp]}]").is_err()
+    );
+    assert!(parse(
+        "templates: [{name: a, extends: [b]}, {name: b, extends: [a]}]\n\
+         rules: [{name: fav, extends: [a], regex: ['^_'], watch_dirs: [/tmp]}]"
+    )
+    .is_err());
+
+    // expect the same from a builder
+    let config = Config::builder()
+        .template(
+            Rule::builder("samples")
+                .watch_dir("/tmp/Samples")
+                .regex("^_"),
+        )
+        .rule(
+            Rule::builder("fav")
+                .extends("samples")
+                .link_dir("/tmp/Favorites"),
+        )
+        .build()
+        .unwrap();
+    assert_eq!(config.rules[0].watch_dirs, [PathBuf::from("/tmp/Samples")]);
+    assert_eq!(config.rules[0].regex[0].as_str(), "^_");
+}
+
+#[tokio::test]
+async fn watch_dir_globs() {
+    // init
+    let (_, root) = create_test_env();
+    let (tx, rx) = create_tx_rx!();
+    let _logger = Logger::new();
+    let _span = info_span!("T_watch_dir_globs").entered();
+
+    // create dirs
+    let_paths!(
+        (packs = root / "packs"              : create = "dir"),
+        (fav_dir = root / "fav_dir"          : create = "dir"),
+        (pack_a = packs / "a"                : create = "dir"),
+        (samples_a = pack_a / "Samples"      : create = "dir"),
+        (pack_b = packs / "b"                : create = "dir"),
+        (other_b = pack_b / "Other"          : create = "dir"),
+        (pack_c = packs / "c"                : create = "no"),
+        (samples_c = pack_c / "Samples"      : create = "no"),
+    );
+
+    // create files
+    let_paths!(
+        // in a matching dir at startup, expect a link
+        (file1 = samples_a / "_kick.wav" : create = "f"),
+        // in a dir that doesn't match, expect no link
+        (file2 = other_b / "_snare.wav"  : create = "f"),
+    );
+
+    // define config
+    let raw_config = RawConfig {
+        rules: vec![Rule {
+            name: "fav".into(),
+            watch_dirs: vec![packs.join("*").join("Samples")],
+            link_dirs: vec![fav_dir.clone()],
+            regex: vec![Regex::new("^_").unwrap()],
+            ..Rule::default()
+        }],
+        ..RawConfig::default()
+    };
+    let config = config_from_raw(&raw_config);
+    assert_eq!(config.rules[0].watch_dirs, vec![samples_a.clone()]);
+
+    let test_hook = {
+        clone_vars!(tx, samples_c, pack_a);
+        move || {
+            // a new pack, expect it to be found and its contents linked
+            fs::create_dir_all(&samples_c).unwrap();
+            fs::write(samples_c.join("_hat.wav"), "").unwrap();
+
+            thread::sleep(Duration::from_millis(500));
+            // expect new files in it to be linked by its watcher
+            fs::write(samples_c.join("_clap.wav"), "").unwrap();
+            // a pack removed, expect the links into it to be cleaned
+            fs::remove_dir_all(&pack_a).unwrap();
+
+            thread::sleep(Duration::from_millis(500));
+            send_shutdown(&tx);
+        }
+    };
+
+    // start the main process loop
+    run_with_config(config, tx, rx, Some(test_hook))
+        .await
+        .expect("failed to run main");
+
+    // assertions
+    assert_cur_and_exp_trees_eq(
+        &root,
+        vec![
+            "fav_dir",
+            "fav_dir/_clap.wav",
+            "fav_dir/_hat.wav",
+            "packs",
+            "packs/b",
+            "packs/b/Other",
+            "packs/b/Other/_snare.wav",
+            "packs/c",
+            "packs/c/Samples",
+            "packs/c/Samples/_clap.wav",
+            "packs/c/Samples/_hat.wav",
+        ],
+    );
+}